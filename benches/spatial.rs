@@ -0,0 +1,95 @@
+use ggez::nalgebra as na;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::Instant;
+
+use why_cellar_is_safe::gamesettings::GameSettings;
+use why_cellar_is_safe::spatial::SpatialGrid;
+use why_cellar_is_safe::{Input, Simulation};
+
+// Radius of a typical query: player radius plus the whole sneeze.
+const QUERY_RADIUS: f32 = 55.0;
+
+// World size which keeps the density of the default settings for `n` citizens.
+fn world_size(n: usize) -> (f32, f32) {
+    let settings = GameSettings::default();
+    let scale = (n as f32 / settings.get_citizens_quan() as f32).sqrt();
+    (
+        settings.get_world_width() * scale,
+        settings.get_world_height() * scale,
+    )
+}
+
+fn bench_queries(n: usize) {
+    let (width, height) = world_size(n);
+    let mut rng = StdRng::seed_from_u64(0);
+    let points: Vec<na::Point2<f32>> = (0..n)
+        .map(|_| na::Point2::new(rng.gen_range(0.0, width), rng.gen_range(0.0, height)))
+        .collect();
+    let queries: Vec<na::Point2<f32>> = points.iter().take(1000).cloned().collect();
+
+    let mut grid = SpatialGrid::new(width, height, 50.0);
+    let start = Instant::now();
+    grid.rebuild(points.iter().cloned().enumerate());
+    let rebuild = start.elapsed();
+
+    let start = Instant::now();
+    let mut found = 0;
+    for q in queries.iter() {
+        found += grid.query_radius(*q, QUERY_RADIUS).len();
+    }
+    let grid_queries = start.elapsed();
+
+    let start = Instant::now();
+    let mut linear_found = 0;
+    for q in queries.iter() {
+        linear_found += points
+            .iter()
+            .filter(|p| na::distance(q, p) <= QUERY_RADIUS)
+            .count();
+    }
+    let linear_queries = start.elapsed();
+    assert_eq!(found, linear_found);
+
+    println!(
+        "{:>7} points: rebuild {:>9.3} ms, 1000 queries {:>9.3} ms (linear scan {:>9.3} ms)",
+        n,
+        rebuild.as_secs_f64() * 1000.0,
+        grid_queries.as_secs_f64() * 1000.0,
+        linear_queries.as_secs_f64() * 1000.0,
+    );
+}
+
+fn bench_simulation(n: usize, steps: u32) {
+    let (width, height) = world_size(n);
+    let settings = GameSettings::default()
+        .with_overrides(&[
+            ("world_width".to_string(), width.to_string()),
+            ("world_height".to_string(), height.to_string()),
+            ("citizen_quantity".to_string(), n.to_string()),
+        ])
+        .unwrap();
+    let mut sim = Simulation::new(settings, 0);
+    let input = Input::default();
+
+    let start = Instant::now();
+    for _ in 0..steps {
+        sim.step(&input);
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{:>7} citizens: {:>9.3} ms per simulation step",
+        n,
+        elapsed.as_secs_f64() * 1000.0 / f64::from(steps),
+    );
+}
+
+fn main() {
+    for &n in &[1_000, 10_000, 100_000] {
+        bench_queries(n);
+    }
+    for &n in &[1_000, 10_000, 100_000] {
+        bench_simulation(n, 100);
+    }
+}
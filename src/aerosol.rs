@@ -0,0 +1,309 @@
+use ggez::nalgebra as na;
+use ggez::{graphics, Context, GameResult};
+use serde::{Deserialize, Serialize};
+
+use crate::attack::Cone;
+use crate::map::Map;
+
+// Concentrations lower than this are cleared, so that clouds do not last forever.
+const CLEAR_AIR: f32 = 0.1;
+// Concentration drawn with the full alpha of `sneeze_color`.
+const FULL_CONCENTRATION: f32 = 30.0;
+// Part of a cell that can move to a single neighbour in a step, larger
+// values make the diffusion unstable.
+const MAX_EXCHANGE: f32 = 0.15;
+
+// Sneezes and coughs leave clouds of aerosol, kept on a grid of square cells
+// of `cell_size`. Clouds lose half of their concentration every `half_life`
+// ticks, spread out at the rate of `diffusion` (in square units per second)
+// and are blown by the wind (in units per second). Buildings stop them.
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AerosolParams {
+    cell_size: f32,
+    half_life: u32,
+    diffusion: f32,
+    wind_x: f32,
+    wind_y: f32,
+}
+
+impl AerosolParams {
+    pub fn get_cell_size(self) -> f32 {
+        self.cell_size
+    }
+
+    pub fn get_half_life(self) -> u32 {
+        self.half_life
+    }
+
+    pub fn get_diffusion(self) -> f32 {
+        self.diffusion
+    }
+
+    pub fn get_wind(self) -> na::Vector2<f32> {
+        na::Vector2::new(self.wind_x, self.wind_y)
+    }
+}
+
+impl Default for AerosolParams {
+    fn default() -> Self {
+        AerosolParams {
+            cell_size: 10.0,
+            half_life: 180,
+            diffusion: 40.0,
+            wind_x: 10.0,
+            wind_y: 0.0,
+        }
+    }
+}
+
+// Concentration of aerosol over the town. A citizen in a cell with
+// concentration `c` takes a viral dose of `c` per second.
+pub struct AerosolField {
+    params: AerosolParams,
+    cols: usize,
+    rows: usize,
+    cells: Vec<f32>,
+    // Cells inside buildings, they never hold any aerosol.
+    blocked: Vec<bool>,
+    // Buffer for the next state of the cells.
+    next: Vec<f32>,
+}
+
+impl AerosolField {
+    pub fn new(params: AerosolParams, map: &Map, width: f32, height: f32) -> AerosolField {
+        let cell_size = params.cell_size.max(1.0);
+        let cols = ((width / cell_size).ceil() as usize).max(1);
+        let rows = ((height / cell_size).ceil() as usize).max(1);
+        let mut field = AerosolField {
+            params: AerosolParams {
+                cell_size,
+                ..params
+            },
+            cols,
+            rows,
+            cells: vec![0.0; cols * rows],
+            blocked: vec![false; cols * rows],
+            next: vec![0.0; cols * rows],
+        };
+        for cell in 0..cols * rows {
+            field.blocked[cell] = map.is_blocked(field.center(cell), 0.0);
+        }
+        field
+    }
+
+    fn center(&self, cell: usize) -> na::Point2<f32> {
+        na::Point2::new(
+            ((cell % self.cols) as f32 + 0.5) * self.params.cell_size,
+            ((cell / self.cols) as f32 + 0.5) * self.params.cell_size,
+        )
+    }
+
+    // Cell next to the given one, `None` for the edges of the town.
+    fn neighbour(&self, cell: usize, dx: i32, dy: i32) -> Option<usize> {
+        let x = (cell % self.cols) as i32 + dx;
+        let y = (cell / self.cols) as i32 + dy;
+        if x < 0 || y < 0 || x >= self.cols as i32 || y >= self.rows as i32 {
+            None
+        } else {
+            Some(y as usize * self.cols + x as usize)
+        }
+    }
+
+    pub fn concentration_at(&self, p: na::Point2<f32>) -> f32 {
+        if p.x < 0.0 || p.y < 0.0 {
+            return 0.0;
+        }
+        let x = (p.x / self.params.cell_size) as usize;
+        let y = (p.y / self.params.cell_size) as usize;
+        if x >= self.cols || y >= self.rows {
+            return 0.0;
+        }
+        self.cells[y * self.cols + x]
+    }
+
+    // Adds `amount` to every cell the cone covers and which can be seen from its apex.
+    pub fn emit(&mut self, cone: Cone, amount: f32, map: &Map) {
+        let apex = cone.get_apex();
+        let reach = cone.get_reach();
+        let size = self.params.cell_size;
+        let min_x = ((apex.x - reach) / size).floor().max(0.0) as usize;
+        let min_y = ((apex.y - reach) / size).floor().max(0.0) as usize;
+        let max_x = (((apex.x + reach) / size).ceil().max(0.0) as usize).min(self.cols);
+        let max_y = (((apex.y + reach) / size).ceil().max(0.0) as usize).min(self.rows);
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let cell = y * self.cols + x;
+                let center = self.center(cell);
+                if !self.blocked[cell] && cone.hits(center, 0.0) && map.is_visible(apex, center) {
+                    self.cells[cell] += amount;
+                }
+            }
+        }
+    }
+
+    // Advances the clouds by a single tick of `dt` seconds.
+    pub fn update(&mut self, dt: f32) {
+        let decay = 0.5f32.powf(1.0 / self.params.half_life.max(1) as f32);
+        let size = self.params.cell_size;
+        let exchange = (self.params.diffusion * dt / (size * size)).min(MAX_EXCHANGE);
+        let wind = self.params.get_wind() * dt / size;
+        let drift_x = wind.x.abs().min(MAX_EXCHANGE);
+        let drift_y = wind.y.abs().min(MAX_EXCHANGE);
+        let step_x = if wind.x < 0.0 { -1 } else { 1 };
+        let step_y = if wind.y < 0.0 { -1 } else { 1 };
+
+        for c in self.next.iter_mut() {
+            *c = 0.0;
+        }
+        for cell in 0..self.cells.len() {
+            let c = self.cells[cell] * decay;
+            if self.blocked[cell] || c == 0.0 {
+                continue;
+            }
+            let mut left = c;
+            // Spreads to the neighbours, what leaves the town is lost.
+            for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)].iter() {
+                match self.neighbour(cell, *dx, *dy) {
+                    Some(n) if self.blocked[n] => continue,
+                    Some(n) => self.next[n] += c * exchange,
+                    None => (),
+                }
+                left -= c * exchange;
+            }
+            // The wind blows a part of the rest downwind, walls hold it back.
+            for (drift, dx, dy) in [(drift_x, step_x, 0), (drift_y, 0, step_y)].iter() {
+                let moved = c * drift;
+                match self.neighbour(cell, *dx, *dy) {
+                    Some(n) if self.blocked[n] => continue,
+                    Some(n) => self.next[n] += moved,
+                    None => (),
+                }
+                left -= moved;
+            }
+            self.next[cell] += left;
+        }
+        for c in self.next.iter_mut() {
+            if *c < CLEAR_AIR {
+                *c = 0.0;
+            }
+        }
+        std::mem::swap(&mut self.cells, &mut self.next);
+    }
+
+    // Draws the cells within the view, denser clouds are less transparent.
+    pub fn draw_aerosol(
+        &self,
+        ctx: &mut Context,
+        color: graphics::Color,
+        view: graphics::Rect,
+    ) -> GameResult {
+        let size = self.params.cell_size;
+        let min_x = (view.x / size).floor().max(0.0) as usize;
+        let min_y = (view.y / size).floor().max(0.0) as usize;
+        let max_x = (((view.x + view.w) / size).ceil().max(0.0) as usize).min(self.cols);
+        let max_y = (((view.y + view.h) / size).ceil().max(0.0) as usize).min(self.rows);
+        let mut mesh = graphics::MeshBuilder::new();
+        let mut empty = true;
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let c = self.cells[y * self.cols + x];
+                if c == 0.0 {
+                    continue;
+                }
+                let alpha = color.a * (c / FULL_CONCENTRATION).min(1.0);
+                mesh.rectangle(
+                    graphics::DrawMode::fill(),
+                    graphics::Rect::new(x as f32 * size, y as f32 * size, size, size),
+                    graphics::Color::new(color.r, color.g, color.b, alpha),
+                );
+                empty = false;
+            }
+        }
+        if empty {
+            return Ok(());
+        }
+        let mesh = mesh.build(ctx)?;
+        graphics::draw(ctx, &mesh, graphics::DrawParam::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Obstacle;
+
+    fn params(half_life: u32, diffusion: f32, wind_x: f32) -> AerosolParams {
+        AerosolParams {
+            cell_size: 10.0,
+            half_life,
+            diffusion,
+            wind_x,
+            wind_y: 0.0,
+        }
+    }
+
+    fn sneeze_at(x: f32, y: f32, reach: f32) -> Cone {
+        Cone::new(
+            na::Point2::new(x, y),
+            na::Vector2::new(1.0, 0.0),
+            reach,
+            360.0,
+        )
+    }
+
+    fn total(field: &AerosolField) -> f32 {
+        field.cells.iter().sum()
+    }
+
+    #[test]
+    fn spreading_keeps_the_aerosol() {
+        let map = Map::default();
+        let mut field = AerosolField::new(params(u32::MAX, 40.0, 0.0), &map, 200.0, 200.0);
+        // A dense cloud, so that the traces cleared at its edges do not count.
+        field.emit(sneeze_at(100.0, 100.0, 15.0), 100_000.0, &map);
+        let emitted = total(&field);
+        let middle = field.concentration_at(na::Point2::new(100.0, 100.0));
+        assert!(emitted > 0.0);
+        for _ in 0..120 {
+            field.update(1.0 / 60.0);
+        }
+        assert!((total(&field) - emitted).abs() < emitted * 1e-3);
+        assert!(field.concentration_at(na::Point2::new(100.0, 100.0)) < middle);
+        assert!(field.concentration_at(na::Point2::new(135.0, 100.0)) > 0.0);
+    }
+
+    #[test]
+    fn clouds_fade_with_the_half_life() {
+        let map = Map::default();
+        let mut field = AerosolField::new(params(60, 0.0, 0.0), &map, 200.0, 200.0);
+        field.emit(sneeze_at(100.0, 100.0, 15.0), 100.0, &map);
+        let emitted = total(&field);
+        for half in 1..=3 {
+            for _ in 0..60 {
+                field.update(1.0 / 60.0);
+            }
+            let expected = emitted * 0.5f32.powi(half);
+            assert!((total(&field) - expected).abs() < expected * 1e-3);
+        }
+    }
+
+    #[test]
+    fn buildings_stay_clear() {
+        let map = Map::new(vec![Obstacle::rect(100.0, 0.0, 20.0, 200.0)]);
+        let mut field = AerosolField::new(params(180, 40.0, 30.0), &map, 200.0, 200.0);
+        assert!(field.blocked.iter().any(|&b| b));
+        for _ in 0..300 {
+            field.emit(sneeze_at(60.0, 100.0, 80.0), 10.0, &map);
+            field.update(1.0 / 60.0);
+            for (cell, &c) in field.cells.iter().enumerate() {
+                if field.blocked[cell] {
+                    assert_eq!(c, 0.0);
+                }
+            }
+        }
+        assert!(field.concentration_at(na::Point2::new(95.0, 100.0)) > 0.0);
+        // Nothing gets through to the other side of the wall either.
+        assert_eq!(field.concentration_at(na::Point2::new(125.0, 100.0)), 0.0);
+    }
+}
@@ -1,63 +1,90 @@
 use ggez::nalgebra as na;
 use ggez::{graphics, Context, GameResult};
-use rand;
 use rand::Rng;
 
+use crate::gamesettings::GameSettings;
+use crate::moveable::Boundary;
 use crate::moveable::EntityParams;
 use crate::moveable::Moveable;
-use crate::moveable::Zone;
 
+pub const RADIUS: f32 = 10.0;
+
+// Distance from the destination that counts as being there, as collisions
+// may leave the ambulance a little off.
+const ARRIVAL_DISTANCE: f32 = 0.5;
+
+// Ambulances drive along routes planned by the simulation, `destination` is
+// the next point of the route.
 #[derive(Copy, Clone)]
 pub struct Ambulance {
     ent_params: EntityParams,
     pub destination: na::Point2<f32>,
     is_free: bool,
     patient: Option<usize>,
+    // Hospital the patient is taken to.
+    hospital: usize,
+    carries_patient: bool,
 }
 
 impl Ambulance {
     pub fn finish_act(&mut self) {
         self.is_free = true;
         self.patient = None;
+        self.carries_patient = false;
+        self.destination = self.get_position();
     }
 
     pub fn is_free(self) -> bool {
         self.is_free
     }
 
-    pub fn set_destination(&mut self, cid: usize, dest: na::Point2<f32>, width: f32, height: f32) {
+    pub fn head_to(&mut self, dest: na::Point2<f32>) {
         self.destination = dest;
+        let way = dest - self.get_position();
+        if way.norm() > 0.0 {
+            self.ent_params.set_velocity(way.normalize());
+        }
+    }
+
+    // The ambulance goes for the patient from where it is now, `dest` is the
+    // first point of the way.
+    pub fn set_destination(&mut self, cid: usize, dest: na::Point2<f32>, hospital: usize) {
         self.patient = Some(cid);
+        self.hospital = hospital;
         self.is_free = false;
-        let mut rng = rand::thread_rng();
-        let zone: Zone = rand::random();
-        let r = self.get_radius();
-        let start = match zone {
-            Zone::LeftBorder => na::Point2::new(-r, rng.gen_range(0.0, height)),
-            Zone::RightBorder => na::Point2::new(width + r, rng.gen_range(0.0, height)),
-            Zone::UpBorder => na::Point2::new(rng.gen_range(0.0, width), -r),
-            Zone::BottomBorder => na::Point2::new(rng.gen_range(0.0, width), height + r),
-            _ => na::Point2::new(r, r),
-        };
-        self.ent_params.set_cx(start.x);
-        self.ent_params.set_cy(start.y);
-        self.ent_params
-            .set_velocity((self.destination - start).normalize());
-    }
-
-    pub fn draw_ambulance(&mut self, ctx: &mut Context) -> GameResult {
-        let image = graphics::Image::new(ctx, "/ambulance2.png")?;
+        self.carries_patient = false;
+        self.head_to(dest);
+    }
+
+    pub fn pick_up(&mut self, dest: na::Point2<f32>) {
+        self.carries_patient = true;
+        self.head_to(dest);
+    }
+
+    pub fn carries_patient(self) -> bool {
+        self.carries_patient
+    }
+
+    pub fn has_arrived(self) -> bool {
+        na::distance(&self.get_position(), &self.destination) <= ARRIVAL_DISTANCE
+    }
+
+    pub fn draw_ambulance(self, ctx: &mut Context, image: &graphics::Image) -> GameResult {
         let drawparams = graphics::DrawParam::new()
             .dest(self.get_position())
             .rotation(0.0)
             .offset(na::Point2::new(0.5, 0.5));
 
-        graphics::draw(ctx, &image, drawparams)
+        graphics::draw(ctx, image, drawparams)
     }
 
     pub fn get_patient_id(self) -> Option<usize> {
         self.patient
     }
+
+    pub fn get_hospital_id(self) -> usize {
+        self.hospital
+    }
 }
 
 impl Moveable for Ambulance {
@@ -73,43 +100,46 @@ impl Moveable for Ambulance {
         self.ent_params.get_speed()
     }
 
-    fn move_being(&mut self, width: f32, height: f32) {
-        match self.ent_params.where_is(width, height) {
-            Zone::Inside => {
-                self.ent_params.move_step();
-                if na::distance(&self.destination, &self.get_position())
-                    <= self.ent_params.get_radius()
-                {
-                    self.ent_params.come_back();
-                }
-            }
-            _ => {
-                if !self.is_free() {
-                    self.finish_act();
-                }
-            }
+    fn set_position(&mut self, p: na::Point2<f32>) {
+        self.ent_params.set_center(p);
+    }
+
+    fn move_being<R: Rng + ?Sized>(
+        &mut self,
+        boundary: Boundary,
+        world: (f32, f32),
+        dt: f32,
+        rng: &mut R,
+    ) {
+        if self.is_free() || self.has_arrived() {
+            return;
+        }
+        // Stop exactly at the destination instead of driving past it. Aim again
+        // every step, as walls may push the ambulance aside.
+        if na::distance(&self.destination, &self.get_position()) <= self.get_speed() * dt {
+            self.ent_params.set_center(self.destination);
+        } else {
+            self.head_to(self.destination);
+            self.ent_params.move_step(dt);
         }
+        self.ent_params.keep_inside(boundary, world, rng);
     }
 }
 
-pub fn new(width: f32, height: f32, dest: na::Point2<f32>) -> Ambulance {
-    let mut rng = rand::thread_rng();
-    let zone: Zone = rand::random();
-    let r = 10.0;
-    let start = match zone {
-        Zone::LeftBorder => na::Point2::new(-r, rng.gen_range(0.0, height)),
-        Zone::RightBorder => na::Point2::new(width + r, rng.gen_range(0.0, height)),
-        Zone::UpBorder => na::Point2::new(rng.gen_range(0.0, width), -r),
-        Zone::BottomBorder => na::Point2::new(rng.gen_range(0.0, width), height + r),
-        _ => na::Point2::new(r, r),
-    };
-    let vect: na::Vector2<f32> = (dest - start).normalize();
-
-    let ent_params = EntityParams::new(start, r, vect, 10.0);
+// New ambulances wait at the hospital.
+pub fn new(settings: GameSettings, start: na::Point2<f32>) -> Ambulance {
+    let ent_params = EntityParams::new(
+        start,
+        RADIUS,
+        na::Vector2::new(0.0, 0.0),
+        settings.get_ambulance_speed(),
+    );
     Ambulance {
-        ent_params: ent_params,
-        destination: dest,
-        is_free: false,
+        ent_params,
+        destination: start,
+        is_free: true,
         patient: None,
+        hospital: 0,
+        carries_patient: false,
     }
 }
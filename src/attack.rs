@@ -0,0 +1,309 @@
+use ggez::nalgebra as na;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::f32::consts::PI;
+
+// Number of segments of the arc of a drawn cone.
+const ARC_SEGMENTS: usize = 24;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AttackKind {
+    Sneeze,
+    Cough,
+}
+
+// The attack grows from `range` to `max_range` at `speed` units per second,
+// then waits `cooldown` ticks and starts again while the button is held. `angle`
+// is the full width of the cone in degrees, 360 hits all around the player. It
+// uses `stamina_cost` stamina per second and gives citizens `dose` of the virus
+// per second, less for the ones further away, see `DoseParams`.
+// With positive `charge_time` the attack is charged while the button is held and
+// bursts out when it is released. After `charge_time` ticks it is fully charged
+// and reaches `charge_bonus` times further.
+// Every second of the attack adds `aerosol` to the clouds in the cells it covers.
+#[derive(Copy, Clone, Serialize)]
+pub struct AttackParams {
+    range: f32,
+    max_range: f32,
+    speed: f32,
+    angle: f32,
+    dose: f32,
+    stamina_cost: f32,
+    cooldown: u32,
+    charge_time: u32,
+    charge_bonus: f32,
+    aerosol: f32,
+}
+
+impl AttackParams {
+    pub fn get_range(self) -> f32 {
+        self.range
+    }
+
+    pub fn get_max_range(self) -> f32 {
+        self.max_range
+    }
+
+    pub fn get_speed(self) -> f32 {
+        self.speed
+    }
+
+    pub fn get_angle(self) -> f32 {
+        self.angle
+    }
+
+    pub fn get_dose(self) -> f32 {
+        self.dose
+    }
+
+    pub fn get_stamina_cost(self) -> f32 {
+        self.stamina_cost
+    }
+
+    pub fn get_cooldown(self) -> u32 {
+        self.cooldown
+    }
+
+    pub fn get_charge_time(self) -> u32 {
+        self.charge_time
+    }
+
+    pub fn get_charge_bonus(self) -> f32 {
+        self.charge_bonus
+    }
+
+    pub fn get_aerosol(self) -> f32 {
+        self.aerosol
+    }
+
+    // Same attack reaching `factor` times as far.
+    pub fn with_reach(self, factor: f32) -> AttackParams {
+        AttackParams {
+            max_range: self.max_range * factor,
+            ..self
+        }
+    }
+
+    pub fn sneeze() -> AttackParams {
+        AttackParams {
+            range: 5.0,
+            max_range: 30.0,
+            speed: 600.0,
+            angle: 90.0,
+            dose: 600.0,
+            stamina_cost: 50.0,
+            cooldown: 20,
+            charge_time: 0,
+            charge_bonus: 2.0,
+            aerosol: 600.0,
+        }
+    }
+
+    pub fn cough() -> AttackParams {
+        AttackParams {
+            range: 0.0,
+            max_range: 15.0,
+            speed: 300.0,
+            angle: 180.0,
+            dose: 180.0,
+            stamina_cost: 20.0,
+            cooldown: 0,
+            charge_time: 0,
+            charge_bonus: 1.5,
+            aerosol: 60.0,
+        }
+    }
+}
+
+// Fields missing in a settings table keep the default values of the attack
+// the table describes.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AttackDef {
+    range: Option<f32>,
+    max_range: Option<f32>,
+    speed: Option<f32>,
+    angle: Option<f32>,
+    dose: Option<f32>,
+    stamina_cost: Option<f32>,
+    cooldown: Option<u32>,
+    charge_time: Option<u32>,
+    charge_bonus: Option<f32>,
+    aerosol: Option<f32>,
+}
+
+impl AttackDef {
+    fn with_defaults(self, defaults: AttackParams) -> AttackParams {
+        AttackParams {
+            range: self.range.unwrap_or(defaults.range),
+            max_range: self.max_range.unwrap_or(defaults.max_range),
+            speed: self.speed.unwrap_or(defaults.speed),
+            angle: self.angle.unwrap_or(defaults.angle),
+            dose: self.dose.unwrap_or(defaults.dose),
+            stamina_cost: self.stamina_cost.unwrap_or(defaults.stamina_cost),
+            cooldown: self.cooldown.unwrap_or(defaults.cooldown),
+            charge_time: self.charge_time.unwrap_or(defaults.charge_time),
+            charge_bonus: self.charge_bonus.unwrap_or(defaults.charge_bonus),
+            aerosol: self.aerosol.unwrap_or(defaults.aerosol),
+        }
+    }
+}
+
+pub fn deserialize_sneeze<'de, D: Deserializer<'de>>(d: D) -> Result<AttackParams, D::Error> {
+    AttackDef::deserialize(d).map(|def| def.with_defaults(AttackParams::sneeze()))
+}
+
+pub fn deserialize_cough<'de, D: Deserializer<'de>>(d: D) -> Result<AttackParams, D::Error> {
+    AttackDef::deserialize(d).map(|def| def.with_defaults(AttackParams::cough()))
+}
+
+// Attacks use stamina, which comes back at `regeneration` per second while the
+// player neither attacks nor charges an attack. A player who runs out of it cannot attack until a
+// quarter of it comes back.
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StaminaParams {
+    max: f32,
+    regeneration: f32,
+}
+
+impl StaminaParams {
+    pub fn get_max(self) -> f32 {
+        self.max
+    }
+
+    pub fn get_regeneration(self) -> f32 {
+        self.regeneration
+    }
+}
+
+impl Default for StaminaParams {
+    fn default() -> Self {
+        StaminaParams {
+            max: 100.0,
+            regeneration: 25.0,
+        }
+    }
+}
+
+// Circular sector covered by an attack.
+#[derive(Copy, Clone)]
+pub struct Cone {
+    apex: na::Point2<f32>,
+    // Unit vector along the middle of the cone.
+    direction: na::Vector2<f32>,
+    reach: f32,
+    half_angle: f32,
+}
+
+impl Cone {
+    pub fn new(apex: na::Point2<f32>, direction: na::Vector2<f32>, reach: f32, angle: f32) -> Cone {
+        Cone {
+            apex,
+            direction,
+            reach,
+            half_angle: (angle / 2.0).to_radians().min(PI),
+        }
+    }
+
+    pub fn get_apex(self) -> na::Point2<f32> {
+        self.apex
+    }
+
+    pub fn get_reach(self) -> f32 {
+        self.reach
+    }
+
+    pub fn is_full_circle(self) -> bool {
+        self.half_angle >= PI
+    }
+
+    // True if a circle overlaps the cone. Circles close to the edges of the
+    // cone count as hit when a part of them is inside.
+    pub fn hits(self, center: na::Point2<f32>, radius: f32) -> bool {
+        let offset = center - self.apex;
+        let dist = offset.norm();
+        if dist - radius > self.reach {
+            return false;
+        }
+        if dist <= radius || self.is_full_circle() {
+            return true;
+        }
+        let angle = self.direction.angle(&offset);
+        angle <= self.half_angle + (radius / dist).asin()
+    }
+
+    // Outline of the cone: the apex followed by points of the arc.
+    pub fn get_points(self) -> Vec<na::Point2<f32>> {
+        let start = self.direction.y.atan2(self.direction.x) - self.half_angle;
+        let mut points = vec![self.apex];
+        for i in 0..=ARC_SEGMENTS {
+            let a = start + 2.0 * self.half_angle * i as f32 / ARC_SEGMENTS as f32;
+            points.push(self.apex + na::Vector2::new(a.cos(), a.sin()) * self.reach);
+        }
+        points
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f32, y: f32) -> na::Point2<f32> {
+        na::Point2::new(x, y)
+    }
+
+    fn towards(degrees: f32) -> na::Vector2<f32> {
+        let a = degrees.to_radians();
+        na::Vector2::new(a.cos(), a.sin())
+    }
+
+    #[test]
+    fn full_circle_hits_all_around() {
+        let cone = Cone::new(point(0.0, 0.0), towards(0.0), 10.0, 360.0);
+        assert!(cone.is_full_circle());
+        for i in 0..12 {
+            let v = towards(i as f32 * 30.0);
+            assert!(cone.hits(point(0.0, 0.0) + v * 9.0, 0.0));
+            assert!(cone.hits(point(0.0, 0.0) + v * 11.0, 1.5));
+            assert!(!cone.hits(point(0.0, 0.0) + v * 12.0, 1.5));
+        }
+        assert!(Cone::new(point(0.0, 0.0), towards(0.0), 10.0, 400.0).is_full_circle());
+        assert!(!Cone::new(point(0.0, 0.0), towards(0.0), 10.0, 359.0).is_full_circle());
+    }
+
+    #[test]
+    fn circles_over_the_apex_are_hit() {
+        let cone = Cone::new(point(5.0, 5.0), towards(0.0), 10.0, 30.0);
+        assert!(cone.hits(point(5.0, 5.0), 0.0));
+        assert!(cone.hits(point(5.0, 5.0), 3.0));
+        // Behind the player, but still touching it.
+        assert!(cone.hits(point(3.0, 5.0), 3.0));
+        assert!(!cone.hits(point(1.0, 5.0), 3.0));
+    }
+
+    #[test]
+    fn edges_of_the_cone() {
+        let cone = Cone::new(point(0.0, 0.0), towards(0.0), 10.0, 90.0);
+        assert!(cone.hits(point(0.0, 0.0) + towards(44.0) * 5.0, 0.0));
+        assert!(cone.hits(point(0.0, 0.0) + towards(-44.0) * 5.0, 0.0));
+        assert!(!cone.hits(point(0.0, 0.0) + towards(46.0) * 5.0, 0.0));
+        assert!(!cone.hits(point(0.0, 0.0) + towards(-46.0) * 5.0, 0.0));
+        // Circles just outside of an edge are hit when they reach over it.
+        let outside = point(0.0, 0.0) + towards(50.0) * 5.0;
+        assert!(cone.hits(outside, 0.5));
+        assert!(!cone.hits(outside, 0.3));
+    }
+
+    #[test]
+    fn aim_across_the_back() {
+        // The cone looks to the left, its edges are on both sides of ±180 degrees.
+        for &aim in &[180.0, 175.0, -175.0] {
+            let cone = Cone::new(point(0.0, 0.0), towards(aim), 10.0, 60.0);
+            assert!(cone.hits(point(0.0, 0.0) + towards(aim + 25.0) * 5.0, 0.0));
+            assert!(cone.hits(point(0.0, 0.0) + towards(aim - 25.0) * 5.0, 0.0));
+            assert!(!cone.hits(point(0.0, 0.0) + towards(aim + 35.0) * 5.0, 0.0));
+            assert!(!cone.hits(point(0.0, 0.0) + towards(aim - 35.0) * 5.0, 0.0));
+            assert!(!cone.hits(point(0.0, 0.0) + towards(aim + 180.0) * 5.0, 0.0));
+        }
+    }
+}
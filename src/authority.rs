@@ -0,0 +1,716 @@
+use ggez::nalgebra as na;
+use ggez::{graphics, Context, GameResult};
+use serde::{Deserialize, Serialize};
+
+use crate::map::Obstacle;
+use crate::simulation::{Stats, DT};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Measure {
+    Masks,
+    Lockdown,
+    Testing,
+    Vaccination,
+    Closure,
+}
+
+// How the health authority fights the outbreak. `caution` multiplies the
+// triggers of all measures, lower values act earlier.
+#[derive(Copy, Clone, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    // Does nothing, only ambulances react to the outbreak.
+    None,
+    // Cheap measures first.
+    Balanced,
+    // Acts early, closing areas and testing around known cases.
+    Containment,
+    // Acts late, protecting citizens rather than stopping them.
+    Mitigation,
+}
+
+impl Strategy {
+    // Measures in the order the authority considers them.
+    fn priorities(self) -> &'static [Measure] {
+        match self {
+            Strategy::None => &[],
+            Strategy::Balanced => &[
+                Measure::Masks,
+                Measure::Testing,
+                Measure::Vaccination,
+                Measure::Lockdown,
+                Measure::Closure,
+            ],
+            Strategy::Containment => &[
+                Measure::Closure,
+                Measure::Testing,
+                Measure::Lockdown,
+                Measure::Masks,
+                Measure::Vaccination,
+            ],
+            Strategy::Mitigation => &[
+                Measure::Vaccination,
+                Measure::Masks,
+                Measure::Testing,
+                Measure::Lockdown,
+                Measure::Closure,
+            ],
+        }
+    }
+
+    fn caution(self) -> f32 {
+        match self {
+            Strategy::Containment => 0.5,
+            Strategy::Mitigation => 1.5,
+            _ => 1.0,
+        }
+    }
+}
+
+// Every measure costs `cost` from the budget and lasts `duration` ticks. It
+// is only considered once the share of living citizens known to be sick, the
+// ones with symptoms, reaches `trigger`.
+
+// Citizens pass the virus to each other and take doses from the player
+// `transmission` times as often.
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MasksParams {
+    cost: f32,
+    duration: u32,
+    trigger: f32,
+    transmission: f32,
+}
+
+impl Default for MasksParams {
+    fn default() -> Self {
+        MasksParams {
+            cost: 40.0,
+            duration: 1800,
+            trigger: 0.05,
+            transmission: 0.5,
+        }
+    }
+}
+
+// Citizens walk at `mobility` times their speed.
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LockdownParams {
+    cost: f32,
+    duration: u32,
+    trigger: f32,
+    mobility: f32,
+}
+
+impl Default for LockdownParams {
+    fn default() -> Self {
+        LockdownParams {
+            cost: 80.0,
+            duration: 1200,
+            trigger: 0.2,
+            mobility: 0.3,
+        }
+    }
+}
+
+// Stations are set up where most known cases are, up to `max_stations` at a
+// time. Infected citizens closer than `radius` are found with the chance of
+// `detection` per second and isolated until they get better. The player is
+// held for `hold_time` ticks, unless they slip away, see `player_resistance`.
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TestingParams {
+    cost: f32,
+    duration: u32,
+    trigger: f32,
+    max_stations: u32,
+    radius: f32,
+    detection: f32,
+    hold_time: u32,
+}
+
+impl TestingParams {
+    pub fn get_radius(self) -> f32 {
+        self.radius
+    }
+
+    pub fn get_detection(self) -> f32 {
+        self.detection
+    }
+
+    pub fn get_hold_time(self) -> u32 {
+        self.hold_time
+    }
+}
+
+impl Default for TestingParams {
+    fn default() -> Self {
+        TestingParams {
+            cost: 25.0,
+            duration: 2400,
+            trigger: 0.02,
+            max_stations: 3,
+            radius: 60.0,
+            detection: 0.5,
+            hold_time: 300,
+        }
+    }
+}
+
+// `rate` susceptible citizens a second become immune to all strains known at
+// the time.
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VaccinationParams {
+    cost: f32,
+    duration: u32,
+    trigger: f32,
+    rate: f32,
+}
+
+impl Default for VaccinationParams {
+    fn default() -> Self {
+        VaccinationParams {
+            cost: 60.0,
+            duration: 1800,
+            trigger: 0.1,
+            rate: 1.0,
+        }
+    }
+}
+
+// Squares with the side of `size` around the most known cases are closed to
+// citizens and the player, up to `max_areas` at a time.
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClosureParams {
+    cost: f32,
+    duration: u32,
+    trigger: f32,
+    max_areas: u32,
+    size: f32,
+}
+
+impl ClosureParams {
+    pub fn get_size(self) -> f32 {
+        self.size
+    }
+}
+
+impl Default for ClosureParams {
+    fn default() -> Self {
+        ClosureParams {
+            cost: 50.0,
+            duration: 1200,
+            trigger: 0.1,
+            max_areas: 2,
+            size: 150.0,
+        }
+    }
+}
+
+// The health authority starts with `budget` and gets `income` a second. Every
+// `review_period` ticks it looks at the stats and pays for at most one measure.
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthorityParams {
+    strategy: Strategy,
+    budget: f32,
+    income: f32,
+    review_period: u32,
+    masks: MasksParams,
+    lockdown: LockdownParams,
+    testing: TestingParams,
+    vaccination: VaccinationParams,
+    closure: ClosureParams,
+}
+
+impl AuthorityParams {
+    pub fn get_strategy(self) -> Strategy {
+        self.strategy
+    }
+
+    pub fn get_review_period(self) -> u32 {
+        self.review_period
+    }
+
+    pub fn get_testing(self) -> TestingParams {
+        self.testing
+    }
+
+    pub fn get_closure(self) -> ClosureParams {
+        self.closure
+    }
+
+    // Cost, duration and trigger of the measure.
+    fn terms(self, measure: Measure) -> (f32, u32, f32) {
+        match measure {
+            Measure::Masks => (self.masks.cost, self.masks.duration, self.masks.trigger),
+            Measure::Lockdown => (
+                self.lockdown.cost,
+                self.lockdown.duration,
+                self.lockdown.trigger,
+            ),
+            Measure::Testing => (
+                self.testing.cost,
+                self.testing.duration,
+                self.testing.trigger,
+            ),
+            Measure::Vaccination => (
+                self.vaccination.cost,
+                self.vaccination.duration,
+                self.vaccination.trigger,
+            ),
+            Measure::Closure => (
+                self.closure.cost,
+                self.closure.duration,
+                self.closure.trigger,
+            ),
+        }
+    }
+
+    pub fn validate(self) -> Result<(), String> {
+        if self.budget < 0.0 || self.income < 0.0 {
+            return Err("budget and income must not be negative".to_string());
+        }
+        if self.review_period == 0 {
+            return Err("review_period must be greater than zero".to_string());
+        }
+        let measures = [
+            ("masks", Measure::Masks),
+            ("lockdown", Measure::Lockdown),
+            ("testing", Measure::Testing),
+            ("vaccination", Measure::Vaccination),
+            ("closure", Measure::Closure),
+        ];
+        for (name, measure) in measures.iter() {
+            let (cost, duration, trigger) = self.terms(*measure);
+            if cost < 0.0 || trigger < 0.0 {
+                return Err(format!("cost and trigger of {} must not be negative", name));
+            }
+            if duration == 0 {
+                return Err(format!("duration of {} must be greater than zero", name));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.masks.transmission) {
+            return Err("masks.transmission must be between 0.0 and 1.0".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.lockdown.mobility) {
+            return Err("lockdown.mobility must be between 0.0 and 1.0".to_string());
+        }
+        if self.testing.radius < 0.0 || self.testing.detection < 0.0 {
+            return Err("testing.radius and testing.detection must not be negative".to_string());
+        }
+        if self.vaccination.rate < 0.0 {
+            return Err("vaccination.rate must not be negative".to_string());
+        }
+        if self.closure.size <= 0.0 {
+            return Err("closure.size must be greater than zero".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Default for AuthorityParams {
+    fn default() -> Self {
+        AuthorityParams {
+            strategy: Strategy::Balanced,
+            budget: 100.0,
+            income: 2.0,
+            review_period: 300,
+            masks: MasksParams::default(),
+            lockdown: LockdownParams::default(),
+            testing: TestingParams::default(),
+            vaccination: VaccinationParams::default(),
+            closure: ClosureParams::default(),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Station {
+    position: na::Point2<f32>,
+    ticks_left: u32,
+}
+
+impl Station {
+    pub fn get_position(self) -> na::Point2<f32> {
+        self.position
+    }
+}
+
+#[derive(Clone)]
+pub struct Closure {
+    area: Obstacle,
+    center: na::Point2<f32>,
+    ticks_left: u32,
+}
+
+impl Closure {
+    pub fn get_area(&self) -> &Obstacle {
+        &self.area
+    }
+}
+
+// Opponent of the player, which only knows what the stats tell and pays for
+// countermeasures from its budget.
+pub struct Authority {
+    params: AuthorityParams,
+    budget: f32,
+    // Ticks left of the measures in force all over the town.
+    masks: u32,
+    lockdown: u32,
+    vaccination: u32,
+    // Citizens due for vaccination, with the fraction of the next one.
+    vaccines: f32,
+    stations: Vec<Station>,
+    closures: Vec<Closure>,
+    deployed: u32,
+}
+
+impl Authority {
+    pub fn new(params: AuthorityParams) -> Authority {
+        Authority {
+            params,
+            budget: params.budget,
+            masks: 0,
+            lockdown: 0,
+            vaccination: 0,
+            vaccines: 0.0,
+            stations: Vec::new(),
+            closures: Vec::new(),
+            deployed: 0,
+        }
+    }
+
+    pub fn get_params(&self) -> AuthorityParams {
+        self.params
+    }
+
+    pub fn get_budget(&self) -> f32 {
+        self.budget
+    }
+
+    // Number of measures paid for so far.
+    pub fn get_deployed(&self) -> u32 {
+        self.deployed
+    }
+
+    // Ticks left of the measure in force, zero if it is not.
+    pub fn get_remaining(&self, measure: Measure) -> u32 {
+        match measure {
+            Measure::Masks => Some(self.masks),
+            Measure::Lockdown => Some(self.lockdown),
+            Measure::Vaccination => Some(self.vaccination),
+            Measure::Testing => self.stations.iter().map(|s| s.ticks_left).max(),
+            Measure::Closure => self.closures.iter().map(|c| c.ticks_left).max(),
+        }
+        .unwrap_or(0)
+    }
+
+    pub fn get_stations(&self) -> &[Station] {
+        &self.stations
+    }
+
+    pub fn get_closures(&self) -> &[Closure] {
+        &self.closures
+    }
+
+    // Multiplies the chance of passing the virus and the dose taken.
+    pub fn get_transmission(&self) -> f32 {
+        if self.masks > 0 {
+            self.params.masks.transmission
+        } else {
+            1.0
+        }
+    }
+
+    // Multiplies the speed of citizens.
+    pub fn get_mobility(&self) -> f32 {
+        if self.lockdown > 0 {
+            self.params.lockdown.mobility
+        } else {
+            1.0
+        }
+    }
+
+    // Number of citizens to vaccinate in this tick.
+    pub fn take_vaccines(&mut self) -> u32 {
+        if self.vaccination == 0 {
+            return 0;
+        }
+        self.vaccines += self.params.vaccination.rate * DT;
+        let due = self.vaccines.floor();
+        self.vaccines -= due;
+        due as u32
+    }
+
+    // Pays the income and ends measures which ran out.
+    pub fn update(&mut self) {
+        self.budget += self.params.income * DT;
+        self.masks = self.masks.saturating_sub(1);
+        self.lockdown = self.lockdown.saturating_sub(1);
+        self.vaccination = self.vaccination.saturating_sub(1);
+        if self.vaccination == 0 {
+            self.vaccines = 0.0;
+        }
+        for station in self.stations.iter_mut() {
+            station.ticks_left = station.ticks_left.saturating_sub(1);
+        }
+        self.stations.retain(|s| s.ticks_left > 0);
+        for closure in self.closures.iter_mut() {
+            closure.ticks_left = closure.ticks_left.saturating_sub(1);
+        }
+        self.closures.retain(|c| c.ticks_left > 0);
+    }
+
+    fn can_deploy(&self, measure: Measure, hotspot: Option<na::Point2<f32>>) -> bool {
+        match measure {
+            Measure::Masks => self.masks == 0,
+            Measure::Lockdown => self.lockdown == 0,
+            Measure::Vaccination => self.vaccination == 0,
+            // Places already covered are not covered again.
+            Measure::Testing => match hotspot {
+                Some(p) => {
+                    self.stations.len() < self.params.testing.max_stations as usize
+                        && self
+                            .stations
+                            .iter()
+                            .all(|s| na::distance(&s.position, &p) > self.params.testing.radius)
+                }
+                None => false,
+            },
+            Measure::Closure => match hotspot {
+                Some(p) => {
+                    self.closures.len() < self.params.closure.max_areas as usize
+                        && self.closures.iter().all(|c| !c.area.contains(p))
+                }
+                None => false,
+            },
+        }
+    }
+
+    // Picks the first measure of the strategy which the share of known cases
+    // calls for and the budget allows. `hotspot` finds the place with most
+    // known cases, for measures placed in the town.
+    pub fn review<F>(&mut self, stats: &Stats, mut hotspot: F) -> Option<Measure>
+    where
+        F: FnMut() -> Option<na::Point2<f32>>,
+    {
+        let living = stats.citizens - stats.dead;
+        if living == 0 {
+            return None;
+        }
+        let share = stats.symptomatic as f32 / living as f32;
+        let strategy = self.params.strategy;
+        let mut spot = None;
+        for &measure in strategy.priorities() {
+            let (cost, duration, trigger) = self.params.terms(measure);
+            if share < trigger * strategy.caution() || self.budget < cost {
+                continue;
+            }
+            if measure == Measure::Testing || measure == Measure::Closure {
+                spot = spot.or_else(|| Some(hotspot()));
+            }
+            let position = spot.flatten();
+            if !self.can_deploy(measure, position) {
+                continue;
+            }
+            self.budget -= cost;
+            self.deployed += 1;
+            match measure {
+                Measure::Masks => self.masks = duration,
+                Measure::Lockdown => self.lockdown = duration,
+                Measure::Vaccination => self.vaccination = duration,
+                Measure::Testing => self.stations.push(Station {
+                    position: position.unwrap(),
+                    ticks_left: duration,
+                }),
+                Measure::Closure => {
+                    let center = position.unwrap();
+                    let half = self.params.closure.size / 2.0;
+                    self.closures.push(Closure {
+                        area: Obstacle::rect(
+                            center.x - half,
+                            center.y - half,
+                            2.0 * half,
+                            2.0 * half,
+                        ),
+                        center,
+                        ticks_left: duration,
+                    })
+                }
+            }
+            return Some(measure);
+        }
+        None
+    }
+
+    pub fn draw_authority(&self, ctx: &mut Context) -> GameResult {
+        for closure in self.closures.iter() {
+            closure
+                .area
+                .draw_obstacle(ctx, graphics::Color::new(0.8, 0.1, 0.1, 0.3))?;
+            let size = self.params.closure.size;
+            let cross = graphics::Mesh::new_line(
+                ctx,
+                &[
+                    closure.center + na::Vector2::new(-size / 2.0, -size / 2.0),
+                    closure.center + na::Vector2::new(size / 2.0, size / 2.0),
+                ],
+                3.0,
+                graphics::Color::new(0.8, 0.1, 0.1, 0.6),
+            )?;
+            graphics::draw(ctx, &cross, graphics::DrawParam::default())?;
+        }
+        for station in self.stations.iter() {
+            let circle = graphics::Mesh::new_circle(
+                ctx,
+                graphics::DrawMode::stroke(2.0),
+                station.position,
+                self.params.testing.radius,
+                2.0,
+                graphics::Color::new(0.2, 0.4, 1.0, 0.8),
+            )?;
+            graphics::draw(ctx, &circle, graphics::DrawParam::default())?;
+            let tent = graphics::Mesh::new_rectangle(
+                ctx,
+                graphics::DrawMode::fill(),
+                graphics::Rect::new(
+                    station.position.x - 8.0,
+                    station.position.y - 8.0,
+                    16.0,
+                    16.0,
+                ),
+                graphics::Color::new(1.0, 1.0, 1.0, 1.0),
+            )?;
+            graphics::draw(ctx, &tent, graphics::DrawParam::default())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn authority(strategy: Strategy, budget: f32) -> Authority {
+        Authority::new(AuthorityParams {
+            strategy,
+            budget,
+            ..AuthorityParams::default()
+        })
+    }
+
+    // Stats of a town of 100 citizens with the given number of known cases.
+    fn stats(symptomatic: usize) -> Stats {
+        Stats {
+            citizens: 100,
+            symptomatic,
+            ..Stats::default()
+        }
+    }
+
+    fn spot() -> Option<na::Point2<f32>> {
+        Some(na::Point2::new(100.0, 100.0))
+    }
+
+    #[test]
+    fn waits_for_the_trigger() {
+        let mut authority = authority(Strategy::Balanced, 100.0);
+        assert_eq!(authority.review(&stats(1), spot), None);
+        assert_eq!(authority.get_budget(), 100.0);
+        // Masks need 5 known cases, testing only 2.
+        assert_eq!(authority.review(&stats(3), spot), Some(Measure::Testing));
+        assert_eq!(authority.review(&stats(5), spot), Some(Measure::Masks));
+    }
+
+    #[test]
+    fn dead_citizens_do_not_count() {
+        let mut authority = authority(Strategy::Balanced, 100.0);
+        let stats = Stats {
+            dead: 60,
+            ..stats(3)
+        };
+        assert_eq!(authority.review(&stats, spot), Some(Measure::Masks));
+    }
+
+    #[test]
+    fn measures_placed_in_town_need_a_hotspot() {
+        let mut authority = authority(Strategy::Containment, 1000.0);
+        assert_eq!(
+            authority.review(&stats(50), || None),
+            Some(Measure::Lockdown)
+        );
+        assert!(authority.get_stations().is_empty());
+        assert!(authority.get_closures().is_empty());
+    }
+
+    #[test]
+    fn stays_within_budget() {
+        let mut authority = authority(Strategy::Balanced, 30.0);
+        // Masks cost 40, testing 25.
+        assert_eq!(authority.review(&stats(50), spot), Some(Measure::Testing));
+        assert_eq!(authority.get_budget(), 5.0);
+        assert_eq!(authority.review(&stats(50), spot), None);
+        assert_eq!(authority.get_budget(), 5.0);
+        assert_eq!(authority.get_deployed(), 1);
+    }
+
+    #[test]
+    fn follows_the_order_of_the_strategy() {
+        let orders = [
+            (
+                Strategy::Balanced,
+                [
+                    Measure::Masks,
+                    Measure::Testing,
+                    Measure::Vaccination,
+                    Measure::Lockdown,
+                    Measure::Closure,
+                ],
+            ),
+            (
+                Strategy::Containment,
+                [
+                    Measure::Closure,
+                    Measure::Testing,
+                    Measure::Lockdown,
+                    Measure::Masks,
+                    Measure::Vaccination,
+                ],
+            ),
+            (
+                Strategy::Mitigation,
+                [
+                    Measure::Vaccination,
+                    Measure::Masks,
+                    Measure::Testing,
+                    Measure::Lockdown,
+                    Measure::Closure,
+                ],
+            ),
+        ];
+        for (strategy, order) in orders.iter() {
+            let mut authority = authority(*strategy, 1000.0);
+            // Measures in force are not deployed again, at the same place neither.
+            for &measure in order.iter() {
+                assert_eq!(authority.review(&stats(50), spot), Some(measure));
+            }
+            assert_eq!(authority.review(&stats(50), spot), None);
+        }
+        let mut authority = authority(Strategy::None, 1000.0);
+        assert_eq!(authority.review(&stats(100), spot), None);
+    }
+
+    #[test]
+    fn caution_scales_triggers() {
+        // Closures need 10 known cases, containment acts at 5.
+        let mut containment = authority(Strategy::Containment, 100.0);
+        assert_eq!(containment.review(&stats(6), spot), Some(Measure::Closure));
+        // Masks need 5 known cases, mitigation waits for 8.
+        let mut mitigation = authority(Strategy::Mitigation, 100.0);
+        assert_eq!(mitigation.review(&stats(6), spot), Some(Measure::Testing));
+    }
+}
@@ -0,0 +1,70 @@
+use ggez::graphics;
+use ggez::nalgebra as na;
+
+// Limits of the zoom, in pixels per unit of the town.
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 4.0;
+// Zoom change for a single notch of the mouse wheel.
+const ZOOM_STEP: f32 = 1.1;
+
+// Part of the town shown in the window. It follows the player, but does not
+// show anything beyond the edges of the town.
+#[derive(Copy, Clone)]
+pub struct Camera {
+    center: na::Point2<f32>,
+    zoom: f32,
+    screen: (f32, f32),
+}
+
+impl Camera {
+    pub fn get_zoom(self) -> f32 {
+        self.zoom
+    }
+
+    // Positive notches zoom in, negative ones zoom out.
+    pub fn zoom_by(&mut self, notches: f32) {
+        self.zoom = (self.zoom * ZOOM_STEP.powf(notches)).clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+
+    pub fn get_screen(self) -> (f32, f32) {
+        self.screen
+    }
+
+    pub fn resize(&mut self, width: f32, height: f32) {
+        self.screen = (width, height);
+    }
+
+    // Centers the view on the target. A town smaller than the view stays in
+    // the middle of the window.
+    pub fn follow(&mut self, target: na::Point2<f32>, (width, height): (f32, f32)) {
+        let fit = |t: f32, view: f32, size: f32| -> f32 {
+            if view >= size {
+                size / 2.0
+            } else {
+                t.clamp(view / 2.0, size - view / 2.0)
+            }
+        };
+        let view = self.get_view();
+        self.center = na::Point2::new(fit(target.x, view.w, width), fit(target.y, view.h, height));
+    }
+
+    // Visible part of the town, in town coordinates.
+    pub fn get_view(self) -> graphics::Rect {
+        let w = self.screen.0 / self.zoom;
+        let h = self.screen.1 / self.zoom;
+        graphics::Rect::new(self.center.x - w / 2.0, self.center.y - h / 2.0, w, h)
+    }
+
+    pub fn to_world(self, p: na::Point2<f32>) -> na::Point2<f32> {
+        let view = self.get_view();
+        na::Point2::new(view.x + p.x / self.zoom, view.y + p.y / self.zoom)
+    }
+}
+
+pub fn new(screen_width: f32, screen_height: f32) -> Camera {
+    Camera {
+        center: na::Point2::new(screen_width / 2.0, screen_height / 2.0),
+        zoom: 1.0,
+        screen: (screen_width, screen_height),
+    }
+}
@@ -0,0 +1,165 @@
+use ggez::nalgebra as na;
+use ggez::{graphics, Context, GameResult};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+// Citizens who hear sneezing closer than `alarm_radius` run to the nearest
+// cellar and stay there for `hide_time` ticks, out of reach of the player.
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CellarParams {
+    quantity: u32,
+    size: f32,
+    capacity: u32,
+    hide_time: u32,
+    alarm_radius: f32,
+}
+
+impl CellarParams {
+    pub fn get_quantity(self) -> u32 {
+        self.quantity
+    }
+
+    pub fn get_size(self) -> f32 {
+        self.size
+    }
+
+    pub fn get_capacity(self) -> u32 {
+        self.capacity
+    }
+
+    pub fn get_hide_time(self) -> u32 {
+        self.hide_time
+    }
+
+    pub fn get_alarm_radius(self) -> f32 {
+        self.alarm_radius
+    }
+
+    pub fn with_alarm_radius(self, alarm_radius: f32) -> CellarParams {
+        CellarParams {
+            alarm_radius,
+            ..self
+        }
+    }
+}
+
+impl Default for CellarParams {
+    fn default() -> Self {
+        CellarParams {
+            quantity: 3,
+            size: 40.0,
+            capacity: 5,
+            hide_time: 300,
+            alarm_radius: 150.0,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Cellar {
+    position: na::Point2<f32>,
+    size: f32,
+    capacity: u32,
+    hide_time: u32,
+    // Hidden citizens with the number of ticks they still stay inside.
+    hidden: Vec<(usize, u32)>,
+}
+
+impl Cellar {
+    pub fn get_position(&self) -> na::Point2<f32> {
+        self.position
+    }
+
+    pub fn get_size(&self) -> f32 {
+        self.size
+    }
+
+    pub fn get_hidden(&self) -> u32 {
+        self.hidden.len() as u32
+    }
+
+    pub fn has_room(&self) -> bool {
+        self.get_hidden() < self.capacity
+    }
+
+    // True if the point is at the cellar door.
+    pub fn is_at_door(&self, p: na::Point2<f32>) -> bool {
+        na::distance(&self.position, &p) <= self.size / 2.0
+    }
+
+    pub fn hide(&mut self, citizen: usize) {
+        self.hidden.push((citizen, self.hide_time));
+    }
+
+    // Advances the time by one tick, returns ids of citizens who come out.
+    pub fn update(&mut self) -> Vec<usize> {
+        for (_, left) in self.hidden.iter_mut() {
+            *left = left.saturating_sub(1);
+        }
+        let leaving = self
+            .hidden
+            .iter()
+            .filter(|(_, left)| *left == 0)
+            .map(|(id, _)| *id)
+            .collect();
+        self.hidden.retain(|(_, left)| *left > 0);
+        leaving
+    }
+
+    pub fn draw_cellar(&self, ctx: &mut Context) -> GameResult {
+        let half = self.size / 2.0;
+        let door = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::fill(),
+            graphics::Rect::new(
+                self.position.x - half,
+                self.position.y - half,
+                self.size,
+                self.size,
+            ),
+            graphics::Color::new(0.35, 0.22, 0.1, 1.0),
+        )?;
+        graphics::draw(ctx, &door, graphics::DrawParam::default())?;
+
+        // Stairs get darker as the cellar fills up.
+        let fill = self.get_hidden() as f32 / self.capacity.max(1) as f32;
+        let stairs = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::fill(),
+            graphics::Rect::new(
+                self.position.x - half / 2.0,
+                self.position.y - half / 2.0,
+                half,
+                half,
+            ),
+            graphics::Color::new(0.2 * (1.0 - fill), 0.12 * (1.0 - fill), 0.05, 1.0),
+        )?;
+        graphics::draw(ctx, &stairs, graphics::DrawParam::default())
+    }
+}
+
+// Cellar at a random place of the screen.
+pub fn random<R: Rng + ?Sized>(
+    params: CellarParams,
+    (width, height): (f32, f32),
+    rng: &mut R,
+) -> Cellar {
+    let half = params.get_size() / 2.0;
+    let position = na::Point2::new(
+        rng.gen_range(half, (width - half).max(half + 1.0)),
+        rng.gen_range(half, (height - half).max(half + 1.0)),
+    );
+    new(params, position, params.get_size())
+}
+
+// Cellar placed by the map.
+pub fn new(params: CellarParams, position: na::Point2<f32>, size: f32) -> Cellar {
+    Cellar {
+        position,
+        size,
+        capacity: params.get_capacity(),
+        hide_time: params.get_hide_time(),
+        hidden: Vec::new(),
+    }
+}
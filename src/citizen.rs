@@ -2,25 +2,40 @@ use ggez::nalgebra as na;
 use ggez::{graphics, Context, GameResult};
 use rand::Rng;
 
+use crate::dose::DoseResponse;
+use crate::gamesettings::{self, GameSettings};
+use crate::infectable::DiseaseParams;
+use crate::infectable::DiseaseState;
 use crate::infectable::Infectable;
 use crate::infectable::InfectionParams;
+use crate::infectable::InfectionSource;
+use crate::moveable::Boundary;
 use crate::moveable::EntityParams;
 use crate::moveable::Moveable;
-use crate::moveable::Zone;
+use crate::strain::Strain;
 
 #[derive(Copy, Clone)]
 pub struct Citizen {
     ent_params: EntityParams,
     inf_params: InfectionParams,
     id: usize,
+    speed: f32,
+    cases: u32,
+    in_hospital: bool,
+    in_cellar: bool,
+    // Found sick by a testing station, stays put until better.
+    isolated: bool,
+    // Cellar the citizen runs to.
+    shelter: Option<usize>,
 }
 
 impl Citizen {
     pub fn draw_citizen(
         self,
         ctx: &mut Context,
-        hcolor: graphics::Color,
-        dcolor: graphics::Color,
+        sick_color: graphics::Color,
+        healthy_color: graphics::Color,
+        reveal: bool,
     ) -> GameResult {
         let circle = graphics::Mesh::new_circle(
             ctx,
@@ -28,26 +43,39 @@ impl Citizen {
             self.get_position(),
             self.get_radius(),
             2.0,
-            self.get_color(hcolor, dcolor),
+            self.get_color(sick_color, healthy_color, reveal),
         )?;
         graphics::draw(ctx, &circle, graphics::DrawParam::default())
     }
 
-    pub fn change_angle(&mut self) {
-        let mut rng = rand::thread_rng();
+    pub fn change_angle<R: Rng + ?Sized>(&mut self, rng: &mut R) {
         let angle: f64 = rng.gen_range(0.0, 360.0);
         let vx = angle.sin() as f32;
         let vy = angle.cos() as f32;
         self.ent_params.set_velocity(na::Vector2::new(vx, vy));
     }
 
-    pub fn get_color(self, hcolor: graphics::Color, dcolor: graphics::Color) -> graphics::Color {
-        let h_to_imm = self.inf_params.helth_to_imm();
-        let calc = |h: f32, d: f32| -> f32 { h - (h_to_imm * (h - d)) };
-        let dr = calc(hcolor.r, dcolor.r);
-        let dg = calc(hcolor.g, dcolor.g);
-        let db = calc(hcolor.b, dcolor.b);
-        let da = calc(hcolor.a, dcolor.a);
+    // Goes from `healthy_color` to `sick_color` as the dose grows. Asymptomatic
+    // carriers look healthy, unless `reveal` is set.
+    pub fn get_color(
+        self,
+        sick_color: graphics::Color,
+        healthy_color: graphics::Color,
+        reveal: bool,
+    ) -> graphics::Color {
+        let h_to_imm = if self.is_asymptomatic() && !reveal {
+            1.0
+        } else {
+            self.inf_params.helth_to_imm()
+        };
+        let calc = |s: f32, h: f32| -> f32 { s - (h_to_imm * (s - h)) };
+        let dr = calc(sick_color.r, healthy_color.r);
+        let dg = calc(sick_color.g, healthy_color.g);
+        let db = calc(sick_color.b, healthy_color.b);
+        let mut da = calc(sick_color.a, healthy_color.a);
+        if self.inf_params.get_state() == DiseaseState::Dead {
+            da *= 0.3;
+        }
         graphics::Color::new(dr, dg, db, da)
     }
 
@@ -56,10 +84,112 @@ impl Citizen {
         self.ent_params.set_cy(p.y);
     }
 
+    pub fn admit_to_hospital(&mut self) {
+        self.in_hospital = true;
+        self.stop();
+    }
+
+    // Leaves the hospital healthy, at the hospital door.
+    pub fn discharge(&mut self, p: na::Point2<f32>) {
+        self.in_hospital = false;
+        self.set_position(p);
+        self.recover();
+    }
+
+    pub fn is_in_hospital(self) -> bool {
+        self.in_hospital
+    }
+
+    pub fn flee_to(&mut self, cellar: usize, p: na::Point2<f32>) {
+        self.shelter = Some(cellar);
+        self.aim_at(p);
+    }
+
+    pub fn aim_at(&mut self, p: na::Point2<f32>) {
+        let way = p - self.get_position();
+        if way.norm() > 0.0 {
+            self.ent_params.set_velocity(way.normalize());
+        }
+    }
+
+    // Keeps walking along the wall the citizen bumped into.
+    pub fn slide_along(&mut self, normal: na::Vector2<f32>) {
+        let v = self.ent_params.get_velocity();
+        let along = v - normal * v.dot(&normal);
+        if along.norm() > 0.1 {
+            self.ent_params.set_velocity(along.normalize());
+        } else {
+            self.ent_params
+                .set_velocity(na::Vector2::new(-normal.y, normal.x));
+        }
+    }
+
+    pub fn get_shelter(self) -> Option<usize> {
+        self.shelter
+    }
+
+    pub fn stop_fleeing(&mut self) {
+        self.shelter = None;
+    }
+
+    pub fn hide_in_cellar(&mut self) {
+        self.in_cellar = true;
+        self.shelter = None;
+    }
+
+    pub fn leave_cellar(&mut self) {
+        self.in_cellar = false;
+    }
+
+    pub fn is_in_cellar(self) -> bool {
+        self.in_cellar
+    }
+
+    // Citizens in hospitals and cellars can neither be met nor seen.
+    pub fn is_hidden(self) -> bool {
+        self.in_hospital || self.in_cellar
+    }
+
     pub fn recover(&mut self) {
         self.inf_params.recover();
     }
 
+    pub fn is_healthy(self) -> bool {
+        self.inf_params.is_healthy()
+    }
+
+    pub fn is_asymptomatic(self) -> bool {
+        self.inf_params.is_asymptomatic()
+    }
+
+    pub fn isolate(&mut self) {
+        self.isolated = true;
+        self.stop();
+    }
+
+    // Lets the citizen go once not infected any more.
+    pub fn release(&mut self) {
+        self.isolated = false;
+        self.ent_params.set_speed(self.speed);
+    }
+
+    pub fn is_isolated(self) -> bool {
+        self.isolated
+    }
+
+    pub fn vaccinate(&mut self, strains: u64) {
+        self.inf_params.vaccinate(strains);
+    }
+
+    pub fn add_cases(&mut self, n: u32) {
+        self.cases += n;
+    }
+
+    // Number of citizens infected by this one.
+    pub fn get_cases(self) -> u32 {
+        self.cases
+    }
+
     pub fn get_id(self) -> usize {
         self.id
     }
@@ -82,26 +212,24 @@ impl Moveable for Citizen {
         self.ent_params.get_speed()
     }
 
-    fn move_being(&mut self, width: f32, height: f32) {
-        let r = self.ent_params.get_radius();
-        match self.ent_params.where_is(width, height) {
-            Zone::LeftBorder => {
-                self.ent_params.set_cx(width + r);
-                self.recover();
-            }
-            Zone::RightBorder => {
-                self.ent_params.set_cx(-r);
-                self.recover();
-            }
-            Zone::BottomBorder => {
-                self.ent_params.set_cy(-r);
-                self.recover();
-            }
-            Zone::UpBorder => {
-                self.ent_params.set_cy(height + r);
-                self.recover();
-            }
-            Zone::Inside => self.ent_params.move_step(),
+    fn set_position(&mut self, p: na::Point2<f32>) {
+        self.ent_params.set_center(p);
+    }
+
+    fn move_being<R: Rng + ?Sized>(
+        &mut self,
+        boundary: Boundary,
+        world: (f32, f32),
+        dt: f32,
+        rng: &mut R,
+    ) {
+        if self.inf_params.get_state() == DiseaseState::Dead || self.is_hidden() {
+            return;
+        }
+        self.ent_params.move_step(dt);
+        // Citizens who leave the town may come back healthy, if the settings say so.
+        if self.ent_params.keep_inside(boundary, world, rng) && boundary == Boundary::Recover {
+            self.recover();
         }
     }
 }
@@ -114,22 +242,85 @@ impl Infectable for Citizen {
     fn get_immunity(self) -> i32 {
         self.inf_params.get_immunity()
     }
-    fn become_infected(&mut self) {
-        self.inf_params.infect();
+
+    fn get_disease_state(self) -> DiseaseState {
+        self.inf_params.get_state()
+    }
+
+    fn absorb_dose<R: Rng + ?Sized>(
+        &mut self,
+        dose: f32,
+        strain: Strain,
+        response: DoseResponse,
+        rng: &mut R,
+    ) -> bool {
+        self.inf_params.infect(dose, strain, response, rng)
+    }
+
+    fn become_exposed<R: Rng + ?Sized>(
+        &mut self,
+        source: InfectionSource,
+        strain: Strain,
+        rng: &mut R,
+    ) -> bool {
+        self.inf_params.expose(source, strain, rng)
+    }
+
+    fn get_strain(self) -> Option<Strain> {
+        self.inf_params.get_strain()
     }
 
-    fn cure(&mut self) {
-        self.inf_params.cure();
+    fn get_infection_source(self) -> Option<InfectionSource> {
+        self.inf_params.get_source()
+    }
+
+    fn get_susceptibility(self) -> f32 {
+        self.inf_params.get_susceptibility()
+    }
+
+    fn progress_disease<R: Rng + ?Sized>(&mut self, disease: DiseaseParams, rng: &mut R) {
+        self.inf_params.progress(disease, rng);
+    }
+
+    fn clear_dose(&mut self, clearance: u32) {
+        self.inf_params.clear(clearance);
         if self.inf_params.is_healthy() {
-            self.ent_params.set_speed(5.0);
+            self.ent_params.set_speed(self.speed);
         }
     }
 }
 
-pub fn random_citizen(width: f32, height: f32, id: usize) -> Citizen {
+pub fn random_citizen<R: Rng + ?Sized>(
+    settings: GameSettings,
+    id: usize,
+    (width, height): (f32, f32),
+    rng: &mut R,
+) -> Citizen {
+    let radius = settings
+        .get_citizen_radius()
+        .sample(gamesettings::MIN_CITIZEN_RADIUS, rng);
+    let speed = settings
+        .get_citizen_speed()
+        .sample(gamesettings::MIN_CITIZEN_SPEED, rng);
+    let immunity = settings
+        .get_citizen_immunity()
+        .sample(gamesettings::MIN_CITIZEN_IMMUNITY, rng);
+    let susceptibility = settings
+        .get_citizen_susceptibility()
+        .sample(gamesettings::MIN_CITIZEN_SUSCEPTIBILITY, rng);
     Citizen {
         id,
-        ent_params: EntityParams::random((width, height)),
-        inf_params: InfectionParams::default(),
+        ent_params: EntityParams::random((width, height), radius, speed, rng),
+        inf_params: InfectionParams::new(
+            immunity.round() as i32,
+            susceptibility,
+            settings.get_disease().get_asymptomatic_rate(),
+        ),
+        speed,
+        cases: 0,
+        in_hospital: false,
+        in_cellar: false,
+        isolated: false,
+        shelter: None,
     }
 }
@@ -0,0 +1,153 @@
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Copy, Clone, PartialEq)]
+enum CallState {
+    Queued,
+    Assigned,
+    PickedUp,
+}
+
+#[derive(Copy, Clone)]
+struct Call {
+    tick: u64,
+    state: CallState,
+}
+
+// Keeps patients waiting for an ambulance in the order they called for it
+// and measures how long they waited to be picked up. Times are in ticks.
+#[derive(Default)]
+pub struct Dispatcher {
+    queue: VecDeque<usize>,
+    calls: HashMap<usize, Call>,
+    served: u32,
+    total_wait: u64,
+    max_wait: u64,
+}
+
+impl Dispatcher {
+    pub fn new() -> Dispatcher {
+        Dispatcher::default()
+    }
+
+    // Patients that already called are not queued again.
+    pub fn call(&mut self, patient: usize, tick: u64) {
+        if self.calls.contains_key(&patient) {
+            return;
+        }
+        self.calls.insert(
+            patient,
+            Call {
+                tick,
+                state: CallState::Queued,
+            },
+        );
+        self.queue.push_back(patient);
+    }
+
+    // Forgets the patient, e.g. when he got better before the ambulance came.
+    pub fn cancel(&mut self, patient: usize) {
+        if let Some(call) = self.calls.remove(&patient) {
+            if call.state == CallState::Queued {
+                self.queue.retain(|&p| p != patient);
+            }
+        }
+    }
+
+    // The patient who waits the longest for an ambulance.
+    pub fn next_patient(&self) -> Option<usize> {
+        self.queue.front().copied()
+    }
+
+    pub fn assign(&mut self, patient: usize) {
+        if let Some(call) = self.calls.get_mut(&patient) {
+            if call.state == CallState::Queued {
+                call.state = CallState::Assigned;
+                self.queue.retain(|&p| p != patient);
+            }
+        }
+    }
+
+    pub fn pick_up(&mut self, patient: usize, tick: u64) {
+        if let Some(call) = self.calls.get_mut(&patient) {
+            if call.state != CallState::PickedUp {
+                call.state = CallState::PickedUp;
+                let wait = tick - call.tick;
+                self.served += 1;
+                self.total_wait += wait;
+                self.max_wait = self.max_wait.max(wait);
+            }
+        }
+    }
+
+    pub fn get_queue_len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn get_served(&self) -> u32 {
+        self.served
+    }
+
+    pub fn get_average_wait(&self) -> f32 {
+        if self.served == 0 {
+            0.0
+        } else {
+            self.total_wait as f32 / self.served as f32
+        }
+    }
+
+    pub fn get_max_wait(&self) -> u64 {
+        self.max_wait
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serves_patients_in_order_of_calls() {
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.call(3, 10);
+        dispatcher.call(1, 20);
+        dispatcher.call(2, 30);
+        // Calling again keeps the place in the queue.
+        dispatcher.call(3, 40);
+        assert_eq!(dispatcher.get_queue_len(), 3);
+
+        let mut order = Vec::new();
+        while let Some(patient) = dispatcher.next_patient() {
+            dispatcher.assign(patient);
+            order.push(patient);
+        }
+        assert_eq!(order, vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn cancelled_patients_leave_the_queue() {
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.call(0, 0);
+        dispatcher.call(1, 0);
+        dispatcher.call(2, 0);
+        dispatcher.cancel(0);
+        assert_eq!(dispatcher.next_patient(), Some(1));
+        dispatcher.cancel(2);
+        dispatcher.assign(1);
+        assert_eq!(dispatcher.next_patient(), None);
+    }
+
+    #[test]
+    fn measures_waits() {
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.call(0, 0);
+        dispatcher.call(1, 10);
+        dispatcher.assign(0);
+        dispatcher.pick_up(0, 30);
+        dispatcher.assign(1);
+        dispatcher.pick_up(1, 20);
+        // Picking up twice counts once.
+        dispatcher.pick_up(1, 100);
+        assert_eq!(dispatcher.get_served(), 2);
+        assert_eq!(dispatcher.get_max_wait(), 30);
+        assert_eq!(dispatcher.get_average_wait(), 20.0);
+    }
+}
@@ -0,0 +1,171 @@
+use rand::Rng;
+use rand_distr::{Distribution, Gamma, LogNormal, Normal};
+use serde::{Deserialize, Serialize};
+
+// Value of a citizen parameter: either the same number for everybody,
+// e.g. `citizen_speed = 300.0`, or a distribution it is drawn from,
+// e.g. `citizen_speed = { distribution = "normal", mean = 300.0, std_dev = 60.0 }`.
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ParamDistribution {
+    Constant(f32),
+    Random(RandomParam),
+}
+
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(tag = "distribution", rename_all = "snake_case", deny_unknown_fields)]
+pub enum RandomParam {
+    Uniform { min: f32, max: f32 },
+    Normal { mean: f32, std_dev: f32 },
+    Gamma { shape: f32, scale: f32 },
+    LogNormal { mu: f32, sigma: f32 },
+}
+
+impl ParamDistribution {
+    // Checks parameters of the distribution and that all values it
+    // can give are not smaller than `min`.
+    pub fn validate(self, min: f32) -> Result<(), String> {
+        match self {
+            ParamDistribution::Constant(value) => {
+                if value >= min {
+                    Ok(())
+                } else {
+                    Err(format!("must not be smaller than {}", min))
+                }
+            }
+            ParamDistribution::Random(RandomParam::Uniform {
+                min: low,
+                max: high,
+            }) => {
+                if low > high {
+                    Err("`min` must not be greater than `max`".to_string())
+                } else if low < min {
+                    Err(format!("`min` must not be smaller than {}", min))
+                } else {
+                    Ok(())
+                }
+            }
+            ParamDistribution::Random(RandomParam::Normal { std_dev, .. }) => {
+                if std_dev >= 0.0 {
+                    Ok(())
+                } else {
+                    Err("`std_dev` must not be negative".to_string())
+                }
+            }
+            ParamDistribution::Random(RandomParam::Gamma { shape, scale }) => {
+                if shape > 0.0 && scale > 0.0 {
+                    Ok(())
+                } else {
+                    Err("`shape` and `scale` must be greater than zero".to_string())
+                }
+            }
+            ParamDistribution::Random(RandomParam::LogNormal { sigma, .. }) => {
+                if sigma >= 0.0 {
+                    Ok(())
+                } else {
+                    Err("`sigma` must not be negative".to_string())
+                }
+            }
+        }
+    }
+
+    // Draws a value, values smaller than `min` are raised to `min`.
+    pub fn sample<R: Rng + ?Sized>(self, min: f32, rng: &mut R) -> f32 {
+        let value = match self {
+            ParamDistribution::Constant(value) => value,
+            ParamDistribution::Random(RandomParam::Uniform {
+                min: low,
+                max: high,
+            }) => {
+                if low < high {
+                    rng.gen_range(low, high)
+                } else {
+                    low
+                }
+            }
+            ParamDistribution::Random(RandomParam::Normal { mean, std_dev }) => {
+                match Normal::new(mean, std_dev) {
+                    Ok(d) => d.sample(rng),
+                    Err(_) => mean,
+                }
+            }
+            ParamDistribution::Random(RandomParam::Gamma { shape, scale }) => {
+                match Gamma::new(shape, scale) {
+                    Ok(d) => d.sample(rng),
+                    Err(_) => shape * scale,
+                }
+            }
+            ParamDistribution::Random(RandomParam::LogNormal { mu, sigma }) => {
+                match LogNormal::new(mu, sigma) {
+                    Ok(d) => d.sample(rng),
+                    Err(_) => mu.exp(),
+                }
+            }
+        };
+        value.max(min)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamesettings::{
+        MIN_CITIZEN_IMMUNITY, MIN_CITIZEN_RADIUS, MIN_CITIZEN_SPEED, MIN_CITIZEN_SUSCEPTIBILITY,
+    };
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    // Distributions giving many values below any of the minimums.
+    fn wide() -> Vec<ParamDistribution> {
+        vec![
+            ParamDistribution::Constant(-10.0),
+            ParamDistribution::Random(RandomParam::Uniform {
+                min: -10.0,
+                max: 10.0,
+            }),
+            ParamDistribution::Random(RandomParam::Normal {
+                mean: 0.0,
+                std_dev: 5.0,
+            }),
+            ParamDistribution::Random(RandomParam::Gamma {
+                shape: 0.5,
+                scale: 1.0,
+            }),
+            ParamDistribution::Random(RandomParam::LogNormal {
+                mu: -2.0,
+                sigma: 1.0,
+            }),
+        ]
+    }
+
+    #[test]
+    fn samples_are_clamped_to_minimums() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let minimums = [
+            MIN_CITIZEN_RADIUS,
+            MIN_CITIZEN_SPEED,
+            MIN_CITIZEN_IMMUNITY,
+            MIN_CITIZEN_SUSCEPTIBILITY,
+        ];
+        for distribution in wide() {
+            for &min in minimums.iter() {
+                let samples: Vec<f32> = (0..1000)
+                    .map(|_| distribution.sample(min, &mut rng))
+                    .collect();
+                assert!(samples.iter().all(|&v| v >= min));
+            }
+        }
+    }
+
+    #[test]
+    fn values_above_minimum_are_kept() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let constant = ParamDistribution::Constant(20.0);
+        assert_eq!(constant.sample(MIN_CITIZEN_RADIUS, &mut rng), 20.0);
+        let uniform = ParamDistribution::Random(RandomParam::Uniform { min: 5.0, max: 6.0 });
+        for _ in 0..100 {
+            let v = uniform.sample(MIN_CITIZEN_RADIUS, &mut rng);
+            assert!((5.0..6.0).contains(&v));
+        }
+    }
+}
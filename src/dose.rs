@@ -0,0 +1,179 @@
+use serde::{Deserialize, Serialize};
+
+// Chance of getting infected after taking a dose of the virus. Doses are
+// measured relative to the immunity of the citizen, which is the dose that
+// infects half of the citizens who take it, e.g.
+// `response = { model = "beta_poisson", alpha = 0.5 }`.
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(tag = "model", rename_all = "snake_case", deny_unknown_fields)]
+pub enum DoseResponse {
+    // Always infected once the dose is over the immunity, never before. Like
+    // for the other models, the immunity itself infects half of the citizens.
+    Threshold,
+    // Every particle has the same chance of starting the infection.
+    Exponential,
+    // Particles differ in how infectious they are, lower `alpha` makes small
+    // doses more dangerous and big ones less.
+    BetaPoisson { alpha: f32 },
+    // S-shaped curve, steeper around the immunity for higher `slope`.
+    Hill { slope: f32 },
+}
+
+impl DoseResponse {
+    // Chance of infection after the total dose `x`, relative to the immunity.
+    pub fn probability(self, x: f32) -> f32 {
+        if x <= 0.0 {
+            return 0.0;
+        }
+        match self {
+            DoseResponse::Threshold => {
+                if x > 1.0 {
+                    1.0
+                } else if x == 1.0 {
+                    0.5
+                } else {
+                    0.0
+                }
+            }
+            DoseResponse::Exponential => 1.0 - 0.5f32.powf(x),
+            DoseResponse::BetaPoisson { alpha } => {
+                1.0 - (1.0 + x * (2f32.powf(1.0 / alpha) - 1.0)).powf(-alpha)
+            }
+            DoseResponse::Hill { slope } => {
+                let y = x.powf(slope);
+                y / (1.0 + y)
+            }
+        }
+    }
+
+    // Chance of infection caused by raising the total dose from `from` to
+    // `to`, for somebody who was not infected by the dose `from`.
+    pub fn probability_between(self, from: f32, to: f32) -> f32 {
+        let before = self.probability(from);
+        if before >= 1.0 {
+            return 1.0;
+        }
+        ((self.probability(to) - before) / (1.0 - before)).max(0.0)
+    }
+
+    pub fn validate(self) -> Result<(), String> {
+        match self {
+            DoseResponse::BetaPoisson { alpha } if alpha <= 0.0 => {
+                Err("`alpha` must be greater than zero".to_string())
+            }
+            DoseResponse::Hill { slope } if slope <= 0.0 => {
+                Err("`slope` must be greater than zero".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// Attacks give the full dose at the player and half of it every
+// `half_distance` units further. Citizens get rid of half of their dose every
+// `clearance` ticks while they are not exposed, zero means never.
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DoseParams {
+    response: DoseResponse,
+    half_distance: f32,
+    clearance: u32,
+}
+
+impl DoseParams {
+    pub fn get_response(self) -> DoseResponse {
+        self.response
+    }
+
+    pub fn get_half_distance(self) -> f32 {
+        self.half_distance
+    }
+
+    pub fn get_clearance(self) -> u32 {
+        self.clearance
+    }
+
+    // Part of the dose left at the given distance from the player.
+    pub fn falloff(self, distance: f32) -> f32 {
+        0.5f32.powf(distance.max(0.0) / self.half_distance)
+    }
+}
+
+impl Default for DoseParams {
+    fn default() -> Self {
+        DoseParams {
+            response: DoseResponse::Exponential,
+            half_distance: 20.0,
+            clearance: 60,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn models() -> Vec<DoseResponse> {
+        vec![
+            DoseResponse::Threshold,
+            DoseResponse::Exponential,
+            DoseResponse::BetaPoisson { alpha: 0.5 },
+            DoseResponse::BetaPoisson { alpha: 3.0 },
+            DoseResponse::Hill { slope: 0.5 },
+            DoseResponse::Hill { slope: 4.0 },
+        ]
+    }
+
+    fn doses() -> Vec<f32> {
+        (0..=100).map(|i| i as f32 * 0.05).collect()
+    }
+
+    #[test]
+    fn immunity_infects_half() {
+        for model in models() {
+            assert!((model.probability(1.0) - 0.5).abs() < 1e-6);
+            assert_eq!(model.probability(0.0), 0.0);
+        }
+    }
+
+    #[test]
+    fn bigger_doses_are_more_dangerous() {
+        for model in models() {
+            let doses = doses();
+            for pair in doses.windows(2) {
+                let (a, b) = (model.probability(pair[0]), model.probability(pair[1]));
+                assert!(a <= b);
+                assert!((0.0..=1.0).contains(&b));
+            }
+        }
+    }
+
+    #[test]
+    fn probability_between_grows_with_the_dose() {
+        for model in models() {
+            let doses = doses();
+            for (i, &from) in doses.iter().enumerate() {
+                if model.probability(from) < 1.0 {
+                    assert_eq!(model.probability_between(from, from), 0.0);
+                }
+                let mut last = 0.0;
+                for &to in doses[i..].iter() {
+                    let p = model.probability_between(from, to);
+                    assert!(p >= last && p <= 1.0);
+                    last = p;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dose_taken_in_parts_infects_like_at_once() {
+        for model in models() {
+            for &(a, b) in [(0.3, 0.8), (0.5, 1.5), (1.2, 3.0)].iter() {
+                let escaped = (1.0 - model.probability_between(0.0, a))
+                    * (1.0 - model.probability_between(a, b));
+                assert!((1.0 - escaped - model.probability(b)).abs() < 1e-5);
+            }
+        }
+    }
+}
@@ -1,15 +1,131 @@
 use ggez::graphics;
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
 
-#[derive(Copy, Clone)]
+use crate::aerosol::AerosolParams;
+use crate::attack::{self, AttackParams, StaminaParams};
+use crate::authority::AuthorityParams;
+use crate::cellar::CellarParams;
+use crate::distribution::ParamDistribution;
+use crate::dose::DoseParams;
+use crate::hospital::HospitalParams;
+use crate::infectable::DiseaseParams;
+use crate::moveable::{Boundaries, Boundary};
+use crate::strain::{MutationParams, Strain};
+use crate::upgrades::Effects;
+
+// Smallest values of citizen parameters drawn from distributions.
+pub const MIN_CITIZEN_RADIUS: f32 = 1.0;
+pub const MIN_CITIZEN_SPEED: f32 = 0.0;
+pub const MIN_CITIZEN_IMMUNITY: f32 = 1.0;
+pub const MIN_CITIZEN_SUSCEPTIBILITY: f32 = 0.0;
+
+#[derive(Deserialize, Serialize)]
+#[serde(remote = "graphics::Color")]
+struct ColorDef {
+    r: f32,
+    g: f32,
+    b: f32,
+    a: f32,
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Invalid { field: &'static str, reason: String },
+    Override(String),
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SettingsError::Io(e) => write!(f, "Cannot read settings: {}", e),
+            SettingsError::Parse(e) => write!(f, "Cannot parse settings: {}", e),
+            SettingsError::Invalid { field, reason } => {
+                write!(f, "Invalid value of `{}`: {}", field, reason)
+            }
+            SettingsError::Override(key) => write!(f, "Cannot override `{}`", key),
+        }
+    }
+}
+
+impl Error for SettingsError {}
+
+impl From<io::Error> for SettingsError {
+    fn from(e: io::Error) -> Self {
+        SettingsError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for SettingsError {
+    fn from(e: toml::de::Error) -> Self {
+        SettingsError::Parse(e)
+    }
+}
+
+// Fields missing in a settings file keep their default values.
+// Speeds are given in units per second.
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct GameSettings {
     screen_width: f32,
     screen_height: f32,
+    // Size of the town, which can be larger than the screen. Maps with a size of their own replace it.
+    world_width: f32,
+    world_height: f32,
     citizen_quantity: i32,
+    // Length of a round in ticks, zero for endless rounds. Headless runs ignore it.
+    round_time: u32,
+    // Tiled map from the `resources` directory, read by `map_name`, so the
+    // settings can stay `Copy`.
+    #[serde(skip_serializing)]
+    map: IgnoredAny,
+    #[serde(with = "ColorDef")]
     bg_color: graphics::Color,
+    #[serde(with = "ColorDef")]
     player_color: graphics::Color,
+    #[serde(with = "ColorDef")]
     health_color: graphics::Color,
+    #[serde(with = "ColorDef")]
     disease_color: graphics::Color,
+    #[serde(with = "ColorDef")]
     sneeze_color: graphics::Color,
+    #[serde(with = "ColorDef")]
+    building_color: graphics::Color,
+    player_radius: f32,
+    player_speed: f32,
+    // Chance of slipping away when a testing station catches the player.
+    player_resistance: f32,
+    #[serde(deserialize_with = "attack::deserialize_sneeze")]
+    sneeze: AttackParams,
+    #[serde(deserialize_with = "attack::deserialize_cough")]
+    cough: AttackParams,
+    stamina: StaminaParams,
+    aerosol: AerosolParams,
+    citizen_radius: ParamDistribution,
+    citizen_speed: ParamDistribution,
+    // Viral dose that infects half of the citizens who take it.
+    citizen_immunity: ParamDistribution,
+    // Multiplies the dose from sneezing and chance of getting the disease from other citizens.
+    citizen_susceptibility: ParamDistribution,
+    ambulance_quantity: i32,
+    ambulance_speed: f32,
+    disease: DiseaseParams,
+    // Strain the player is infected with.
+    strain: Strain,
+    mutation: MutationParams,
+    dose: DoseParams,
+    hospital: HospitalParams,
+    cellars: CellarParams,
+    // Opponent deploying countermeasures against the outbreak.
+    authority: AuthorityParams,
+    boundaries: Boundaries,
 }
 
 impl Default for GameSettings {
@@ -17,7 +133,11 @@ impl Default for GameSettings {
         GameSettings {
             screen_width: 800.0,
             screen_height: 600.0,
+            world_width: 800.0,
+            world_height: 600.0,
             citizen_quantity: 50,
+            round_time: 7200,
+            map: IgnoredAny,
             bg_color: graphics::Color {
                 r: 0.404,
                 g: 0.561,
@@ -48,10 +168,321 @@ impl Default for GameSettings {
                 b: 0.2,
                 a: 0.3,
             },
+            building_color: graphics::Color {
+                r: 0.45,
+                g: 0.42,
+                b: 0.4,
+                a: 1.0,
+            },
+            player_radius: 20.0,
+            player_speed: 300.0,
+            player_resistance: 0.0,
+            sneeze: AttackParams::sneeze(),
+            cough: AttackParams::cough(),
+            stamina: StaminaParams::default(),
+            aerosol: AerosolParams::default(),
+            citizen_radius: ParamDistribution::Constant(20.0),
+            citizen_speed: ParamDistribution::Constant(300.0),
+            citizen_immunity: ParamDistribution::Constant(100.0),
+            citizen_susceptibility: ParamDistribution::Constant(1.0),
+            ambulance_quantity: 1,
+            ambulance_speed: 600.0,
+            disease: DiseaseParams::default(),
+            strain: Strain::default(),
+            mutation: MutationParams::default(),
+            dose: DoseParams::default(),
+            hospital: HospitalParams::default(),
+            cellars: CellarParams::default(),
+            authority: AuthorityParams::default(),
+            boundaries: Boundaries::default(),
+        }
+    }
+}
+
+fn invalid(field: &'static str, reason: &str) -> SettingsError {
+    SettingsError::Invalid {
+        field,
+        reason: reason.to_string(),
+    }
+}
+
+fn check_positive(field: &'static str, value: f32) -> Result<(), SettingsError> {
+    if value > 0.0 {
+        Ok(())
+    } else {
+        Err(invalid(field, "must be greater than zero"))
+    }
+}
+
+fn check_non_negative(field: &'static str, value: f32) -> Result<(), SettingsError> {
+    if value >= 0.0 {
+        Ok(())
+    } else {
+        Err(invalid(field, "must not be negative"))
+    }
+}
+
+fn check_color(field: &'static str, color: graphics::Color) -> Result<(), SettingsError> {
+    let components = [
+        ("r", color.r),
+        ("g", color.g),
+        ("b", color.b),
+        ("a", color.a),
+    ];
+    for (name, value) in components.iter() {
+        if !(0.0..=1.0).contains(value) {
+            return Err(SettingsError::Invalid {
+                field,
+                reason: format!("component `{}` must be between 0.0 and 1.0", name),
+            });
         }
     }
+    Ok(())
+}
+
+fn check_attack(field: &'static str, attack: AttackParams) -> Result<(), SettingsError> {
+    if attack.get_range() < 0.0 || attack.get_max_range() < 0.0 || attack.get_speed() < 0.0 {
+        return Err(invalid(
+            field,
+            "range, max_range and speed must not be negative",
+        ));
+    }
+    if attack.get_angle() <= 0.0 || attack.get_angle() > 360.0 {
+        return Err(invalid(field, "angle must be between 0 and 360 degrees"));
+    }
+    if attack.get_dose() < 0.0 {
+        return Err(invalid(field, "dose must not be negative"));
+    }
+    if attack.get_stamina_cost() < 0.0 {
+        return Err(invalid(field, "stamina_cost must not be negative"));
+    }
+    if attack.get_charge_bonus() < 1.0 {
+        return Err(invalid(field, "charge_bonus must be at least 1.0"));
+    }
+    if attack.get_aerosol() < 0.0 {
+        return Err(invalid(field, "aerosol must not be negative"));
+    }
+    Ok(())
 }
+
+fn check_distribution(
+    field: &'static str,
+    distribution: ParamDistribution,
+    min: f32,
+) -> Result<(), SettingsError> {
+    distribution
+        .validate(min)
+        .map_err(|reason| SettingsError::Invalid { field, reason })
+}
+
 impl GameSettings {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<GameSettings, SettingsError> {
+        let content = fs::read_to_string(path)?;
+        GameSettings::from_toml(&content)
+    }
+
+    pub fn from_toml(content: &str) -> Result<GameSettings, SettingsError> {
+        let settings = toml::from_str::<GameSettings>(content)?.migrate();
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    // Overrides are `(key, value)` pairs, where key may point into a table,
+    // e.g. `("bg_color.r", "0.5")`. Values are parsed as TOML, falling back
+    // to a plain string.
+    pub fn with_overrides(
+        self,
+        overrides: &[(String, String)],
+    ) -> Result<GameSettings, SettingsError> {
+        let mut root = toml::Value::try_from(self)
+            .map_err(|_| SettingsError::Override(String::from("settings")))?;
+        for (key, raw) in overrides {
+            let value = match toml::from_str::<toml::value::Table>(&format!("value = {}", raw)) {
+                Ok(mut table) => table.remove("value").unwrap(),
+                Err(_) => toml::Value::String(raw.clone()),
+            };
+            let mut path: Vec<&str> = key.split('.').collect();
+            let last = path.pop().unwrap();
+            let mut table = &mut root;
+            for part in path {
+                table = table
+                    .get_mut(part)
+                    .ok_or_else(|| SettingsError::Override(key.clone()))?;
+            }
+            match table.as_table_mut() {
+                Some(t) => {
+                    t.insert(last.to_string(), value);
+                }
+                None => return Err(SettingsError::Override(key.clone())),
+            }
+        }
+        let settings = root.try_into::<GameSettings>()?.migrate();
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    // `disease.incubation_period` of older settings files is the incubation
+    // period of the strain of the player.
+    fn migrate(mut self) -> GameSettings {
+        if let Some(incubation_period) = self.disease.take_incubation_period() {
+            self.strain = self.strain.with_incubation_period(incubation_period);
+        }
+        self
+    }
+
+    // Replaces the strain of the player, e.g. with one picked from presets.
+    pub fn with_strain(self, strain: Strain) -> Result<GameSettings, SettingsError> {
+        let settings = GameSettings { strain, ..self };
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    // Applies effects of the upgrades the player bought.
+    pub fn with_upgrades(self, effects: Effects) -> Result<GameSettings, SettingsError> {
+        let resistance = 1.0 - (1.0 - self.player_resistance) * (1.0 - effects.get_resistance());
+        let settings = GameSettings {
+            player_speed: self.player_speed * effects.get_player_speed(),
+            player_resistance: resistance,
+            sneeze: self.sneeze.with_reach(effects.get_sneeze_range()),
+            strain: self.strain.with_transmissibility(
+                self.strain.get_transmissibility() * effects.get_transmissibility(),
+            ),
+            cellars: self
+                .cellars
+                .with_alarm_radius(self.cellars.get_alarm_radius() * effects.get_alarm_radius()),
+            ..self
+        };
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        check_positive("screen_width", self.screen_width)?;
+        check_positive("screen_height", self.screen_height)?;
+        check_positive("world_width", self.world_width)?;
+        check_positive("world_height", self.world_height)?;
+        if self.citizen_quantity < 0 {
+            return Err(invalid("citizen_quantity", "must not be negative"));
+        }
+        check_color("bg_color", self.bg_color)?;
+        check_color("player_color", self.player_color)?;
+        check_color("health_color", self.health_color)?;
+        check_color("disease_color", self.disease_color)?;
+        check_color("sneeze_color", self.sneeze_color)?;
+        check_color("building_color", self.building_color)?;
+        check_positive("player_radius", self.player_radius)?;
+        check_non_negative("player_speed", self.player_speed)?;
+        if !(0.0..=1.0).contains(&self.player_resistance) {
+            return Err(invalid("player_resistance", "must be between 0.0 and 1.0"));
+        }
+        check_attack("sneeze", self.sneeze)?;
+        check_attack("cough", self.cough)?;
+        check_non_negative("stamina.max", self.stamina.get_max())?;
+        check_non_negative("stamina.regeneration", self.stamina.get_regeneration())?;
+        check_positive("aerosol.cell_size", self.aerosol.get_cell_size())?;
+        if self.aerosol.get_half_life() == 0 {
+            return Err(invalid("aerosol.half_life", "must be greater than zero"));
+        }
+        check_non_negative("aerosol.diffusion", self.aerosol.get_diffusion())?;
+        check_distribution("citizen_radius", self.citizen_radius, MIN_CITIZEN_RADIUS)?;
+        check_distribution("citizen_speed", self.citizen_speed, MIN_CITIZEN_SPEED)?;
+        check_distribution(
+            "citizen_immunity",
+            self.citizen_immunity,
+            MIN_CITIZEN_IMMUNITY,
+        )?;
+        check_distribution(
+            "citizen_susceptibility",
+            self.citizen_susceptibility,
+            MIN_CITIZEN_SUSCEPTIBILITY,
+        )?;
+        if self.ambulance_quantity < 0 {
+            return Err(invalid("ambulance_quantity", "must not be negative"));
+        }
+        check_positive("ambulance_speed", self.ambulance_speed)?;
+        let death_rate = self.disease.get_death_rate();
+        if !(0.0..=1.0).contains(&death_rate) {
+            return Err(invalid("disease.death_rate", "must be between 0.0 and 1.0"));
+        }
+        let asymptomatic_rate = self.disease.get_asymptomatic_rate();
+        if !(0.0..=1.0).contains(&asymptomatic_rate) {
+            return Err(invalid(
+                "disease.asymptomatic_rate",
+                "must be between 0.0 and 1.0",
+            ));
+        }
+        let transmission_probability = self.disease.get_transmission_probability();
+        if !(0.0..=1.0).contains(&transmission_probability) {
+            return Err(invalid(
+                "disease.transmission_probability",
+                "must be between 0.0 and 1.0",
+            ));
+        }
+        check_non_negative(
+            "disease.transmission_radius",
+            self.disease.get_transmission_radius(),
+        )?;
+        self.strain
+            .validate()
+            .map_err(|reason| SettingsError::Invalid {
+                field: "strain",
+                reason,
+            })?;
+        let mutation_probability = self.mutation.get_probability();
+        if !(0.0..=1.0).contains(&mutation_probability) {
+            return Err(invalid(
+                "mutation.probability",
+                "must be between 0.0 and 1.0",
+            ));
+        }
+        let variation = self.mutation.get_variation();
+        if !(0.0..=1.0).contains(&variation) {
+            return Err(invalid("mutation.variation", "must be between 0.0 and 1.0"));
+        }
+        self.dose
+            .get_response()
+            .validate()
+            .map_err(|reason| SettingsError::Invalid {
+                field: "dose.response",
+                reason,
+            })?;
+        check_positive("dose.half_distance", self.dose.get_half_distance())?;
+        let hospital = self.hospital.get_position();
+        if !(0.0..=self.world_width).contains(&hospital.x)
+            || !(0.0..=self.world_height).contains(&hospital.y)
+        {
+            return Err(invalid("hospital", "must be placed in the town"));
+        }
+        check_positive("hospital.size", self.hospital.get_size())?;
+        if self.hospital.get_beds() == 0 {
+            return Err(invalid("hospital.beds", "must be greater than zero"));
+        }
+        if self.hospital.get_treatment_time() == 0 {
+            return Err(invalid(
+                "hospital.treatment_time",
+                "must be greater than zero",
+            ));
+        }
+        check_positive("cellars.size", self.cellars.get_size())?;
+        check_non_negative("cellars.alarm_radius", self.cellars.get_alarm_radius())?;
+        // Jumping to another place of the town would take ambulances off their routes.
+        match self.boundaries.get_ambulances() {
+            Boundary::Clamp | Boundary::Bounce => (),
+            _ => {
+                return Err(invalid(
+                    "boundaries.ambulances",
+                    "must be \"clamp\" or \"bounce\"",
+                ))
+            }
+        }
+        self.authority
+            .validate()
+            .map_err(|reason| SettingsError::Invalid {
+                field: "authority",
+                reason,
+            })
+    }
+
     pub fn get_player_col(self) -> graphics::Color {
         self.player_color
     }
@@ -72,14 +503,219 @@ impl GameSettings {
         self.citizen_quantity
     }
 
+    pub fn get_round_time(self) -> u32 {
+        self.round_time
+    }
+
     pub fn get_sneeze_color(self) -> graphics::Color {
         self.sneeze_color
     }
 
+    pub fn get_building_color(self) -> graphics::Color {
+        self.building_color
+    }
+
     pub fn get_screen_width(self) -> f32 {
         self.screen_width
     }
     pub fn get_screen_height(self) -> f32 {
         self.screen_height
     }
+
+    pub fn get_world_width(self) -> f32 {
+        self.world_width
+    }
+
+    pub fn get_world_height(self) -> f32 {
+        self.world_height
+    }
+
+    pub fn get_player_radius(self) -> f32 {
+        self.player_radius
+    }
+
+    pub fn get_player_speed(self) -> f32 {
+        self.player_speed
+    }
+
+    pub fn get_player_resistance(self) -> f32 {
+        self.player_resistance
+    }
+
+    pub fn get_sneeze(self) -> AttackParams {
+        self.sneeze
+    }
+
+    pub fn get_cough(self) -> AttackParams {
+        self.cough
+    }
+
+    pub fn get_stamina(self) -> StaminaParams {
+        self.stamina
+    }
+
+    pub fn get_aerosol(self) -> AerosolParams {
+        self.aerosol
+    }
+
+    pub fn get_citizen_radius(self) -> ParamDistribution {
+        self.citizen_radius
+    }
+
+    pub fn get_citizen_speed(self) -> ParamDistribution {
+        self.citizen_speed
+    }
+
+    pub fn get_citizen_immunity(self) -> ParamDistribution {
+        self.citizen_immunity
+    }
+
+    pub fn get_citizen_susceptibility(self) -> ParamDistribution {
+        self.citizen_susceptibility
+    }
+
+    pub fn get_ambulance_quan(self) -> i32 {
+        self.ambulance_quantity
+    }
+
+    pub fn get_ambulance_speed(self) -> f32 {
+        self.ambulance_speed
+    }
+
+    pub fn get_disease(self) -> DiseaseParams {
+        self.disease
+    }
+
+    pub fn get_strain(self) -> Strain {
+        self.strain
+    }
+
+    pub fn get_mutation(self) -> MutationParams {
+        self.mutation
+    }
+
+    pub fn get_dose(self) -> DoseParams {
+        self.dose
+    }
+
+    pub fn get_hospital(self) -> HospitalParams {
+        self.hospital
+    }
+
+    pub fn get_cellars(self) -> CellarParams {
+        self.cellars
+    }
+
+    pub fn get_authority(self) -> AuthorityParams {
+        self.authority
+    }
+
+    pub fn get_boundaries(self) -> Boundaries {
+        self.boundaries
+    }
+}
+
+#[derive(Deserialize)]
+struct MapName {
+    map: Option<String>,
+}
+
+// Name of the map given by `map` in the content of a settings file.
+pub fn map_name(content: &str) -> Result<Option<String>, SettingsError> {
+    let name: MapName = toml::from_str(content)?;
+    Ok(name.map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejected_field(content: &str) -> &'static str {
+        match GameSettings::from_toml(content) {
+            Err(SettingsError::Invalid { field, .. }) => field,
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("`{}` was accepted", content),
+        }
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(GameSettings::default().validate().is_ok());
+        assert!(
+            GameSettings::from_file(concat!(env!("CARGO_MANIFEST_DIR"), "/settings.toml")).is_ok()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        assert_eq!(rejected_field("screen_width = 0.0"), "screen_width");
+        assert_eq!(rejected_field("screen_height = -1.0"), "screen_height");
+        assert_eq!(rejected_field("citizen_quantity = -1"), "citizen_quantity");
+        assert_eq!(
+            rejected_field("bg_color = { r = 1.5, g = 0.0, b = 0.0, a = 1.0 }"),
+            "bg_color"
+        );
+        assert_eq!(
+            rejected_field("disease_color = { r = 0.0, g = 0.0, b = 0.0, a = -0.1 }"),
+            "disease_color"
+        );
+        assert_eq!(rejected_field("player_radius = 0.0"), "player_radius");
+        assert_eq!(rejected_field("player_speed = -5.0"), "player_speed");
+        assert_eq!(rejected_field("ambulance_speed = 0.0"), "ambulance_speed");
+        assert_eq!(
+            rejected_field("[boundaries]\nambulances = \"wrap\""),
+            "boundaries.ambulances"
+        );
+        assert_eq!(
+            rejected_field("[boundaries]\nambulances = \"respawn\""),
+            "boundaries.ambulances"
+        );
+        assert_eq!(rejected_field("[hospital]\nbeds = 0"), "hospital.beds");
+        assert_eq!(
+            rejected_field("[hospital]\ntreatment_time = 0"),
+            "hospital.treatment_time"
+        );
+    }
+
+    #[test]
+    fn reports_the_component_of_a_color() {
+        match GameSettings::from_toml("sneeze_color = { r = 0.0, g = 2.0, b = 0.0, a = 1.0 }") {
+            Err(SettingsError::Invalid { field, reason }) => {
+                assert_eq!(field, "sneeze_color");
+                assert!(reason.contains("`g`"));
+            }
+            _ => panic!("color out of range was accepted"),
+        }
+    }
+
+    #[test]
+    fn map_is_read_apart() {
+        let content = "map = \"town.tmx\"\ncitizen_quantity = 10";
+        let settings = GameSettings::from_toml(content).unwrap();
+        assert_eq!(settings.get_citizens_quan(), 10);
+        assert_eq!(map_name(content).unwrap(), Some("town.tmx".to_string()));
+        assert_eq!(map_name("citizen_quantity = 10").unwrap(), None);
+    }
+
+    #[test]
+    fn reads_incubation_period_of_the_disease_as_the_strain_one() {
+        let settings = GameSettings::from_toml("[disease]\nincubation_period = 300").unwrap();
+        assert_eq!(settings.get_strain().get_incubation_period(), 300);
+        let settings = settings
+            .with_overrides(&[("disease.incubation_period".to_string(), "90".to_string())])
+            .unwrap();
+        assert_eq!(settings.get_strain().get_incubation_period(), 90);
+        let settings = settings
+            .with_overrides(&[("strain.incubation_period".to_string(), "60".to_string())])
+            .unwrap();
+        assert_eq!(settings.get_strain().get_incubation_period(), 60);
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(matches!(
+            GameSettings::from_toml("citizen_quantiy = 10"),
+            Err(SettingsError::Parse(_))
+        ));
+    }
 }
@@ -0,0 +1,231 @@
+use ggez::nalgebra as na;
+use ggez::{graphics, Context, GameResult};
+use serde::{Deserialize, Serialize};
+
+// Treatment time is given in ticks, see `simulation::TICKS_PER_SECOND`.
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HospitalParams {
+    x: f32,
+    y: f32,
+    size: f32,
+    beds: u32,
+    treatment_time: u32,
+}
+
+impl HospitalParams {
+    pub fn get_position(self) -> na::Point2<f32> {
+        na::Point2::new(self.x, self.y)
+    }
+
+    pub fn get_size(self) -> f32 {
+        self.size
+    }
+
+    pub fn get_beds(self) -> u32 {
+        self.beds
+    }
+
+    pub fn get_treatment_time(self) -> u32 {
+        self.treatment_time
+    }
+}
+
+impl Default for HospitalParams {
+    fn default() -> Self {
+        HospitalParams {
+            x: 700.0,
+            y: 500.0,
+            size: 60.0,
+            beds: 5,
+            treatment_time: 300,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Hospital {
+    position: na::Point2<f32>,
+    size: f32,
+    beds: u32,
+    treatment_time: u32,
+    // Patients in beds with the number of ticks left to their discharge.
+    patients: Vec<(usize, u32)>,
+    // Beds kept for patients an ambulance is already going for.
+    reserved: u32,
+    admitted: u32,
+}
+
+impl Hospital {
+    pub fn get_position(&self) -> na::Point2<f32> {
+        self.position
+    }
+
+    pub fn get_beds(&self) -> u32 {
+        self.beds
+    }
+
+    pub fn get_occupied(&self) -> u32 {
+        self.patients.len() as u32
+    }
+
+    pub fn get_admitted(&self) -> u32 {
+        self.admitted
+    }
+
+    pub fn has_free_bed(&self) -> bool {
+        self.get_occupied() + self.reserved < self.beds
+    }
+
+    pub fn reserve_bed(&mut self) {
+        self.reserved += 1;
+    }
+
+    pub fn cancel_reservation(&mut self) {
+        self.reserved = self.reserved.saturating_sub(1);
+    }
+
+    // Puts the patient into the bed reserved for him.
+    pub fn admit(&mut self, patient: usize) {
+        self.cancel_reservation();
+        self.patients.push((patient, self.treatment_time));
+        self.admitted += 1;
+    }
+
+    // Advances treatment by one tick, returns ids of the discharged patients.
+    pub fn treat(&mut self) -> Vec<usize> {
+        for (_, left) in self.patients.iter_mut() {
+            *left = left.saturating_sub(1);
+        }
+        let discharged = self
+            .patients
+            .iter()
+            .filter(|(_, left)| *left == 0)
+            .map(|(id, _)| *id)
+            .collect();
+        self.patients.retain(|(_, left)| *left > 0);
+        discharged
+    }
+
+    pub fn draw_hospital(&self, ctx: &mut Context) -> GameResult {
+        let half = self.size / 2.0;
+        let building = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::fill(),
+            graphics::Rect::new(
+                self.position.x - half,
+                self.position.y - half,
+                self.size,
+                self.size,
+            ),
+            graphics::WHITE,
+        )?;
+        graphics::draw(ctx, &building, graphics::DrawParam::default())?;
+
+        // Red cross, turns dark when there are no free beds.
+        let color = if self.has_free_bed() {
+            graphics::Color::new(0.8, 0.1, 0.1, 1.0)
+        } else {
+            graphics::Color::new(0.3, 0.1, 0.1, 1.0)
+        };
+        let arm = self.size / 6.0;
+        for rect in [
+            graphics::Rect::new(
+                self.position.x - half / 1.5,
+                self.position.y - arm / 2.0,
+                2.0 * half / 1.5,
+                arm,
+            ),
+            graphics::Rect::new(
+                self.position.x - arm / 2.0,
+                self.position.y - half / 1.5,
+                arm,
+                2.0 * half / 1.5,
+            ),
+        ]
+        .iter()
+        {
+            let cross =
+                graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), *rect, color)?;
+            graphics::draw(ctx, &cross, graphics::DrawParam::default())?;
+        }
+        Ok(())
+    }
+}
+
+pub fn new(params: HospitalParams) -> Hospital {
+    at(params, params.get_position(), params.get_size())
+}
+
+// Hospital placed by the map, with beds and treatment time from the settings.
+pub fn at(params: HospitalParams, position: na::Point2<f32>, size: f32) -> Hospital {
+    Hospital {
+        position,
+        size,
+        beds: params.get_beds(),
+        treatment_time: params.get_treatment_time(),
+        patients: Vec::new(),
+        reserved: 0,
+        admitted: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hospital(beds: u32, treatment_time: u32) -> Hospital {
+        new(HospitalParams {
+            beds,
+            treatment_time,
+            ..HospitalParams::default()
+        })
+    }
+
+    #[test]
+    fn reserves_beds_up_to_capacity() {
+        let mut hospital = hospital(3, 10);
+        for _ in 0..3 {
+            assert!(hospital.has_free_bed());
+            hospital.reserve_bed();
+        }
+        assert!(!hospital.has_free_bed());
+
+        // Admitted patients keep the beds reserved for them.
+        hospital.admit(7);
+        hospital.admit(8);
+        assert_eq!(hospital.get_occupied(), 2);
+        assert!(!hospital.has_free_bed());
+
+        // An ambulance which comes back empty gives its bed back.
+        hospital.cancel_reservation();
+        assert!(hospital.has_free_bed());
+        hospital.reserve_bed();
+        assert!(!hospital.has_free_bed());
+        assert_eq!(hospital.get_admitted(), 2);
+    }
+
+    #[test]
+    fn hospital_without_beds_takes_nobody() {
+        assert!(!hospital(0, 10).has_free_bed());
+    }
+
+    #[test]
+    fn treatment_frees_the_bed() {
+        let mut hospital = hospital(2, 3);
+        hospital.reserve_bed();
+        hospital.admit(4);
+        hospital.reserve_bed();
+        hospital.treat();
+        hospital.admit(5);
+        assert!(!hospital.has_free_bed());
+
+        assert!(hospital.treat().is_empty());
+        assert_eq!(hospital.treat(), vec![4]);
+        assert_eq!(hospital.get_occupied(), 1);
+        assert!(hospital.has_free_bed());
+        assert_eq!(hospital.treat(), vec![5]);
+        assert_eq!(hospital.get_occupied(), 0);
+        assert_eq!(hospital.get_admitted(), 2);
+    }
+}
@@ -1,47 +1,330 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::dose::DoseResponse;
+use crate::strain::Strain;
+
 pub trait Infectable {
     fn get_immunity(self) -> i32;
-    fn become_infected(&mut self);
+    fn get_disease_state(self) -> DiseaseState;
+    // Adds to the viral dose of the strain the being takes, returns true if
+    // it made the being exposed.
+    fn absorb_dose<R: Rng + ?Sized>(
+        &mut self,
+        dose: f32,
+        strain: Strain,
+        response: DoseResponse,
+        rng: &mut R,
+    ) -> bool;
+    // Returns true if the being got exposed to the strain by the contact.
+    fn become_exposed<R: Rng + ?Sized>(
+        &mut self,
+        source: InfectionSource,
+        strain: Strain,
+        rng: &mut R,
+    ) -> bool;
+    // Strain of the current infection.
+    fn get_strain(self) -> Option<Strain>;
+    fn get_infection_source(self) -> Option<InfectionSource>;
+    fn get_susceptibility(self) -> f32;
+    fn progress_disease<R: Rng + ?Sized>(&mut self, disease: DiseaseParams, rng: &mut R);
     fn needs_doctor(&mut self) -> bool;
-    fn cure(&mut self);
+    // Gets rid of a part of the dose, see `DoseParams`.
+    fn clear_dose(&mut self, clearance: u32);
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DiseaseState {
+    Susceptible,
+    Exposed,
+    Infectious,
+    Recovered,
+    Dead,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum InfectionSource {
+    Player,
+    Citizen(usize),
+}
+
+// Durations are given in ticks, see `simulation::TICKS_PER_SECOND`. The
+// incubation period depends on the strain, see `Strain`.
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiseaseParams {
+    infectious_period: u32,
+    // Zero means that immunity never wanes. Immunity to all strains wanes at
+    // once, at the end of the period after the last recovery.
+    immunity_period: u32,
+    // Chance of dying at the end of the infectious period, multiplied by the
+    // severity of the strain.
+    death_rate: f32,
+    // Chance of passing the disease to a susceptible citizen in contact, per
+    // tick, multiplied by the transmissibility of the strain.
+    transmission_probability: f32,
+    // Distance between bodies that counts as a contact.
+    transmission_radius: f32,
+    // Chance that an infected citizen has no symptoms. Such carriers spread
+    // the disease like the others, but look healthy, never call an ambulance
+    // and always recover.
+    asymptomatic_rate: f32,
+    // Old place of the incubation period of the strain of the player, still
+    // read from settings, see `GameSettings::migrate`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    incubation_period: Option<u32>,
+}
+
+impl DiseaseParams {
+    pub fn get_infectious_period(self) -> u32 {
+        self.infectious_period
+    }
+
+    pub fn get_immunity_period(self) -> u32 {
+        self.immunity_period
+    }
+
+    pub fn get_death_rate(self) -> f32 {
+        self.death_rate
+    }
+
+    pub fn get_transmission_probability(self) -> f32 {
+        self.transmission_probability
+    }
+
+    pub fn get_transmission_radius(self) -> f32 {
+        self.transmission_radius
+    }
+
+    pub fn get_asymptomatic_rate(self) -> f32 {
+        self.asymptomatic_rate
+    }
+
+    // Takes the incubation period given the old way out.
+    pub fn take_incubation_period(&mut self) -> Option<u32> {
+        self.incubation_period.take()
+    }
 }
 
+impl Default for DiseaseParams {
+    fn default() -> Self {
+        DiseaseParams {
+            infectious_period: 600,
+            immunity_period: 1800,
+            death_rate: 0.0,
+            transmission_probability: 0.01,
+            transmission_radius: 5.0,
+            asymptomatic_rate: 0.3,
+            incubation_period: None,
+        }
+    }
+}
+
+// Dose smaller than this is cleared at once.
+const CLEAR_DOSE: f32 = 0.5;
+
 #[derive(Copy, Clone)]
 pub struct InfectionParams {
+    // Viral dose that infects half of the citizens taking it.
     immunity: i32,
-    health: i32,
+    // Viral dose taken so far, the whole immunity once infected.
+    dose: f32,
+    state: DiseaseState,
+    ticks_in_state: u32,
+    source: Option<InfectionSource>,
+    susceptibility: f32,
+    asymptomatic_rate: f32,
+    // The current infection has no symptoms.
+    asymptomatic: bool,
+    strain: Option<Strain>,
+    // Bit mask of ids of strains the being is immune to.
+    immune_to: u64,
 }
 
 impl InfectionParams {
+    pub fn new(immunity: i32, susceptibility: f32, asymptomatic_rate: f32) -> InfectionParams {
+        InfectionParams {
+            immunity,
+            susceptibility,
+            asymptomatic_rate,
+            ..InfectionParams::default()
+        }
+    }
+
     pub fn get_immunity(self) -> i32 {
         self.immunity
     }
 
+    pub fn get_state(self) -> DiseaseState {
+        self.state
+    }
+
+    pub fn get_source(self) -> Option<InfectionSource> {
+        self.source
+    }
+
+    pub fn get_susceptibility(self) -> f32 {
+        self.susceptibility
+    }
+
+    // Infected, but without symptoms.
+    pub fn is_asymptomatic(self) -> bool {
+        self.asymptomatic
+    }
+
+    pub fn get_strain(self) -> Option<Strain> {
+        self.strain
+    }
+
+    // Part of the exposures to the strain that do not infect: all of them for
+    // the infected and the ones who had the strain, and the ones the strain
+    // cannot escape for the ones who had other strains.
+    fn protection(self, strain: Strain) -> f32 {
+        match self.state {
+            DiseaseState::Susceptible => 0.0,
+            DiseaseState::Recovered if self.immune_to & (1 << strain.get_id()) == 0 => {
+                1.0 - strain.get_immune_escape()
+            }
+            _ => 1.0,
+        }
+    }
+
     pub fn is_healthy(self) -> bool {
-        self.health == self.immunity
+        self.dose == 0.0
     }
 
     pub fn helth_to_imm(self) -> f32 {
-        (self.health as f32) / (self.immunity as f32)
+        1.0 - (self.dose / self.immunity as f32).min(1.0)
+    }
+
+    fn set_state(&mut self, state: DiseaseState) {
+        self.state = state;
+        self.ticks_in_state = 0;
     }
 
-    pub fn infect(&mut self) {
-        if self.health > 0 {
-            self.health -= 10;
+    // Susceptibility multiplies the dose. Each new part of the dose infects
+    // with the chance the response curve gives for it, lowered by immunity
+    // to other strains.
+    pub fn infect<R: Rng + ?Sized>(
+        &mut self,
+        dose: f32,
+        strain: Strain,
+        response: DoseResponse,
+        rng: &mut R,
+    ) -> bool {
+        let protection = self.protection(strain);
+        if protection >= 1.0 || dose <= 0.0 {
+            return false;
+        }
+        let immunity = self.immunity as f32;
+        let before = self.dose;
+        self.dose += dose * self.susceptibility;
+        let p = response.probability_between(before / immunity, self.dose / immunity)
+            * (1.0 - protection);
+        if p > 0.0 && rng.gen::<f32>() < p {
+            self.set_exposed(InfectionSource::Player, strain, rng);
+            return true;
+        }
+        false
+    }
+
+    pub fn expose<R: Rng + ?Sized>(
+        &mut self,
+        source: InfectionSource,
+        strain: Strain,
+        rng: &mut R,
+    ) -> bool {
+        let protection = self.protection(strain);
+        if protection >= 1.0 || (protection > 0.0 && rng.gen::<f32>() < protection) {
+            return false;
+        }
+        self.set_exposed(source, strain, rng);
+        true
+    }
+
+    fn set_exposed<R: Rng + ?Sized>(
+        &mut self,
+        source: InfectionSource,
+        strain: Strain,
+        rng: &mut R,
+    ) {
+        self.asymptomatic =
+            self.asymptomatic_rate > 0.0 && rng.gen::<f32>() < self.asymptomatic_rate;
+        self.dose = self.immunity as f32;
+        self.source = Some(source);
+        self.strain = Some(strain);
+        self.set_state(DiseaseState::Exposed);
+    }
+
+    pub fn progress<R: Rng + ?Sized>(&mut self, disease: DiseaseParams, rng: &mut R) {
+        self.ticks_in_state += 1;
+        let strain = self.strain.unwrap_or_default();
+        match self.state {
+            DiseaseState::Exposed if self.ticks_in_state >= strain.get_incubation_period() => {
+                self.set_state(DiseaseState::Infectious);
+            }
+            DiseaseState::Infectious if self.ticks_in_state >= disease.infectious_period => {
+                if rng.gen::<f32>() < disease.death_rate * strain.get_severity()
+                    && !self.asymptomatic
+                {
+                    self.set_state(DiseaseState::Dead);
+                } else {
+                    self.recover();
+                }
+            }
+            DiseaseState::Recovered
+                if disease.immunity_period > 0
+                    && self.ticks_in_state >= disease.immunity_period =>
+            {
+                self.set_state(DiseaseState::Susceptible);
+                self.immune_to = 0;
+            }
+            _ => (),
         }
     }
 
     pub fn recover(&mut self) {
-        self.health = self.immunity;
+        match self.state {
+            DiseaseState::Dead => return,
+            DiseaseState::Exposed | DiseaseState::Infectious => {
+                self.set_state(DiseaseState::Recovered)
+            }
+            _ => (),
+        }
+        if let Some(strain) = self.strain.take() {
+            self.immune_to |= 1 << strain.get_id();
+        }
+        self.dose = 0.0;
+        self.asymptomatic = false;
     }
 
-    pub fn cure(&mut self) {
-        if self.health < self.immunity {
-            self.health += 1;
+    // Makes a susceptible being immune to the strains in the bit mask, as if
+    // it had them.
+    pub fn vaccinate(&mut self, strains: u64) {
+        if self.state != DiseaseState::Susceptible {
+            return;
+        }
+        self.set_state(DiseaseState::Recovered);
+        self.immune_to |= strains;
+        self.dose = 0.0;
+    }
+
+    pub fn clear(&mut self, clearance: u32) {
+        let healthy = matches!(
+            self.state,
+            DiseaseState::Susceptible | DiseaseState::Recovered
+        );
+        if !healthy || clearance == 0 {
+            return;
+        }
+        self.dose *= 0.5f32.powf(1.0 / clearance as f32);
+        if self.dose < CLEAR_DOSE {
+            self.dose = 0.0;
         }
     }
 
     pub fn needs_doctor(self) -> bool {
-        self.health <= 0
+        self.state == DiseaseState::Infectious && !self.asymptomatic
     }
 }
 
@@ -49,7 +332,110 @@ impl Default for InfectionParams {
     fn default() -> Self {
         InfectionParams {
             immunity: 100,
-            health: 100,
+            dose: 0.0,
+            state: DiseaseState::Susceptible,
+            ticks_in_state: 0,
+            source: None,
+            susceptibility: 1.0,
+            asymptomatic_rate: 0.0,
+            asymptomatic: false,
+            strain: None,
+            immune_to: 0,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn progress_for(being: &mut InfectionParams, disease: DiseaseParams, ticks: u32) {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..ticks {
+            being.progress(disease, &mut rng);
+        }
+    }
+
+    fn exposed() -> InfectionParams {
+        let mut being = InfectionParams::default();
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(being.expose(InfectionSource::Player, Strain::default(), &mut rng));
+        being
+    }
+
+    #[test]
+    fn goes_through_all_states_in_time() {
+        let disease = DiseaseParams::default();
+        let incubation = Strain::default().get_incubation_period();
+        let mut being = exposed();
+        assert_eq!(being.get_state(), DiseaseState::Exposed);
+
+        progress_for(&mut being, disease, incubation - 1);
+        assert_eq!(being.get_state(), DiseaseState::Exposed);
+        progress_for(&mut being, disease, 1);
+        assert_eq!(being.get_state(), DiseaseState::Infectious);
+
+        progress_for(&mut being, disease, disease.infectious_period - 1);
+        assert_eq!(being.get_state(), DiseaseState::Infectious);
+        progress_for(&mut being, disease, 1);
+        assert_eq!(being.get_state(), DiseaseState::Recovered);
+
+        progress_for(&mut being, disease, disease.immunity_period - 1);
+        assert_eq!(being.get_state(), DiseaseState::Recovered);
+        progress_for(&mut being, disease, 1);
+        assert_eq!(being.get_state(), DiseaseState::Susceptible);
+    }
+
+    #[test]
+    fn immunity_protects_against_known_strains_only() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let first = Strain::default();
+        let escaping: Strain = toml::from_str("immune_escape = 0.3").unwrap();
+        let second = escaping.mutate(1, 0.0, &mut rng);
+        let third = Strain::default().mutate(2, 0.0, &mut rng);
+
+        let healthy = InfectionParams::default();
+        assert_eq!(healthy.protection(first), 0.0);
+
+        let mut being = exposed();
+        assert_eq!(being.protection(second), 1.0);
+        being.recover();
+        assert_eq!(being.protection(first), 1.0);
+        assert!((being.protection(second) - 0.7).abs() < 1e-6);
+        assert_eq!(being.protection(third), 1.0);
+
+        let mut vaccinated = InfectionParams::default();
+        vaccinated.vaccinate(1 << 1);
+        assert_eq!(vaccinated.protection(second), 1.0);
+        assert_eq!(vaccinated.protection(first), 1.0);
+        assert!(!vaccinated.expose(InfectionSource::Player, second, &mut rng));
+    }
+
+    #[test]
+    fn immunity_without_period_never_wanes() {
+        let disease = DiseaseParams {
+            immunity_period: 0,
+            ..DiseaseParams::default()
+        };
+        let mut being = exposed();
+        being.recover();
+        progress_for(&mut being, disease, 100_000);
+        assert_eq!(being.get_state(), DiseaseState::Recovered);
+    }
+
+    #[test]
+    fn dies_at_the_end_of_infectious_period() {
+        let disease = DiseaseParams {
+            death_rate: 1.0,
+            ..DiseaseParams::default()
+        };
+        let mut being = exposed();
+        let incubation = Strain::default().get_incubation_period();
+        progress_for(&mut being, disease, incubation + disease.infectious_period);
+        assert_eq!(being.get_state(), DiseaseState::Dead);
+        being.recover();
+        assert_eq!(being.get_state(), DiseaseState::Dead);
+    }
+}
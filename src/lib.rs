@@ -0,0 +1,25 @@
+pub mod aerosol;
+pub mod ambulance;
+pub mod attack;
+pub mod authority;
+pub mod camera;
+pub mod cellar;
+pub mod citizen;
+pub mod dispatcher;
+pub mod distribution;
+pub mod dose;
+pub mod gamesettings;
+pub mod hospital;
+pub mod infectable;
+pub mod map;
+pub mod minimap;
+pub mod moveable;
+pub mod pathfinding;
+pub mod player;
+pub mod simulation;
+pub mod spatial;
+pub mod strain;
+pub mod tiled;
+pub mod upgrades;
+
+pub use simulation::{Input, Simulation, Stats, DT, TICKS_PER_SECOND};
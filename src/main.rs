@@ -1,42 +1,275 @@
-use ggez::event::{self, EventHandler, MouseButton};
+use ggez::event::{self, EventHandler, KeyCode, KeyMods, MouseButton};
+use ggez::graphics::spritebatch::SpriteBatch;
 use ggez::input;
 use ggez::nalgebra as na;
 use ggez::{graphics, timer, Context, ContextBuilder, GameResult};
-use infectable::Infectable;
-use moveable::Moveable;
-use rand::Rng;
 use std::env;
+use std::fs;
 use std::path;
+use std::process;
+use structopt::StructOpt;
 
-mod ambulance;
-mod citizen;
-mod gamesettings;
-mod infectable;
-mod moveable;
-mod player;
+use why_cellar_is_safe::authority::Measure;
+use why_cellar_is_safe::camera::{self, Camera};
+use why_cellar_is_safe::gamesettings::{self, GameSettings, SettingsError};
+use why_cellar_is_safe::map::Map;
+use why_cellar_is_safe::minimap;
+use why_cellar_is_safe::moveable::Moveable;
+use why_cellar_is_safe::strain;
+use why_cellar_is_safe::upgrades::{Progress, UpgradeTree};
+use why_cellar_is_safe::{Input, Simulation, DT, TICKS_PER_SECOND};
+
+// Width of the stamina bar of the HUD, in pixels.
+const STAMINA_BAR_WIDTH: f32 = 150.0;
+
+#[derive(StructOpt)]
+#[structopt(name = "why-cellar-is-safe", about = "Small epidemic game.")]
+struct Opt {
+    /// Settings file in TOML format
+    #[structopt(short, long, parse(from_os_str), conflicts_with = "scenario")]
+    config: Option<path::PathBuf>,
+
+    /// Name of a settings file from the `scenarios` directory, without extension
+    #[structopt(long)]
+    scenario: Option<String>,
+
+    /// Override a single setting, e.g. `--set citizen_quantity=100` or `--set bg_color.r=0.5`
+    #[structopt(short = "s", long = "set", parse(try_from_str = parse_override), number_of_values = 1)]
+    overrides: Vec<(String, String)>,
+
+    /// Strain from `strains.toml` the player is infected with, replaces `[strain]` from the settings
+    #[structopt(long)]
+    strain: Option<String>,
+
+    /// Map file, either a Tiled map (TMX or JSON) or a TOML file with buildings; overrides `map` from the settings
+    #[structopt(short, long, parse(from_os_str))]
+    map: Option<path::PathBuf>,
+
+    /// File the points and bought upgrades are kept in between rounds
+    #[structopt(long, parse(from_os_str))]
+    progress: Option<path::PathBuf>,
+
+    /// Seed of the random number generator, random if not given
+    #[structopt(long)]
+    seed: Option<u64>,
+
+    /// Window width
+    #[structopt(long)]
+    width: Option<f32>,
+
+    /// Window height
+    #[structopt(long)]
+    height: Option<f32>,
+
+    /// Run in fullscreen mode
+    #[structopt(long)]
+    fullscreen: bool,
+
+    /// Run without a window and print summary statistics
+    #[structopt(long)]
+    headless: bool,
+
+    /// Number of ticks to simulate in headless mode
+    #[structopt(long, default_value = "3600")]
+    ticks: u64,
+
+    /// Keep the player still in headless mode instead of chasing citizens
+    #[structopt(long)]
+    idle: bool,
+}
+
+fn parse_override(s: &str) -> Result<(String, String), String> {
+    match s.find('=') {
+        Some(i) => Ok((s[..i].trim().to_string(), s[i + 1..].trim().to_string())),
+        None => Err(format!("expected `key=value`, got `{}`", s)),
+    }
+}
+
+fn base_dir() -> path::PathBuf {
+    if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
+        path::PathBuf::from(manifest_dir)
+    } else {
+        path::PathBuf::from(".")
+    }
+}
+
+fn read_settings(path: &path::Path) -> Result<(GameSettings, Option<String>), SettingsError> {
+    let content = fs::read_to_string(path)?;
+    Ok((
+        GameSettings::from_toml(&content)?,
+        gamesettings::map_name(&content)?,
+    ))
+}
+
+// Settings with the name of the map they are played on.
+fn load_settings(opt: &Opt) -> Result<(GameSettings, Option<String>), SettingsError> {
+    let (settings, mut map) = match (&opt.config, &opt.scenario) {
+        (Some(path), _) => read_settings(path)?,
+        (None, Some(name)) => {
+            let mut path = base_dir();
+            path.push("scenarios");
+            path.push(format!("{}.toml", name));
+            read_settings(&path)?
+        }
+        (None, None) => (GameSettings::default(), None),
+    };
+
+    // The map is not a part of `GameSettings`.
+    let mut overrides = Vec::new();
+    for (key, value) in opt.overrides.iter() {
+        if key == "map" {
+            map = Some(value.trim_matches('"').to_string());
+        } else {
+            overrides.push((key.clone(), value.clone()));
+        }
+    }
+    if let Some(width) = opt.width {
+        overrides.push(("screen_width".to_string(), width.to_string()));
+    }
+    if let Some(height) = opt.height {
+        overrides.push(("screen_height".to_string(), height.to_string()));
+    }
+    // Overrides apply to the picked strain as well.
+    let settings = match &opt.strain {
+        Some(name) => {
+            let mut path = base_dir();
+            path.push("strains.toml");
+            let mut presets = strain::load_presets(path)?;
+            match presets.remove(name) {
+                Some(strain) => settings.with_strain(strain)?,
+                None => {
+                    return Err(SettingsError::Invalid {
+                        field: "strain",
+                        reason: format!(
+                            "unknown strain `{}`, pick one of: {}",
+                            name,
+                            presets.keys().cloned().collect::<Vec<_>>().join(", ")
+                        ),
+                    })
+                }
+            }
+        }
+        None => settings,
+    };
+    Ok((settings.with_overrides(&overrides)?, map))
+}
+
+// Nobody is at the controls, so the player is steered by the autopilot
+// unless it should stand still.
+fn run_headless(settings: GameSettings, map: Map, seed: u64, ticks: u64, idle: bool) {
+    let mut sim = Simulation::with_map(settings, map, seed);
+    for _ in 0..ticks {
+        let input = if idle {
+            Input::default()
+        } else {
+            sim.autopilot()
+        };
+        sim.step(&input);
+    }
+    println!("Seed: {}", seed);
+    println!("{}", sim.get_stats());
+}
 
 fn main() {
-    // Make place to store resources.
-    let resource_dir = if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
-        let mut path = path::PathBuf::from(manifest_dir);
-        path.push("resources");
+    let opt = Opt::from_args();
+
+    let (settings, map_name) = match load_settings(&opt) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    // Maps named in the settings are looked up in the `resources` directory.
+    let map_path = opt.map.clone().or_else(|| {
+        map_name.map(|name| {
+            let mut path = base_dir();
+            path.push("resources");
+            path.push(name);
+            path
+        })
+    });
+    let map = match map_path {
+        Some(path) => match Map::from_file(path) {
+            Ok(map) => map,
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        },
+        None => Map::default(),
+    };
+
+    // The same seed reproduces a run, so print it for bug reports.
+    let seed = opt.seed.unwrap_or_else(rand::random);
+
+    if opt.headless {
+        run_headless(settings, map, seed, opt.ticks, opt.idle);
+        return;
+    }
+    println!("Seed: {}", seed);
+
+    // Upgrades bought in earlier rounds apply from the start.
+    let mut tree_path = base_dir();
+    tree_path.push("upgrades.toml");
+    let progress_path = opt.progress.clone().unwrap_or_else(|| {
+        let mut path = base_dir();
+        path.push("progress.toml");
         path
-    } else {
-        path::PathBuf::from("./resources")
+    });
+    let shop = UpgradeTree::from_file(tree_path).and_then(|tree| {
+        Progress::load(&progress_path).map(|progress| Shop {
+            tree,
+            progress,
+            path: progress_path,
+            selected: 0,
+            message: String::new(),
+        })
+    });
+    let shop = match shop {
+        Ok(shop) => shop,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let upgraded = match settings.with_upgrades(shop.tree.effects(&shop.progress)) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
     };
 
-    // Make settings, context and the game.
-    let settings = gamesettings::GameSettings::default();
+    // Make place to store resources.
+    let mut resource_dir = base_dir();
+    resource_dir.push("resources");
+
+    // Make context and the game.
+    let fullscreen = if opt.fullscreen {
+        ggez::conf::FullscreenType::True
+    } else {
+        ggez::conf::FullscreenType::Windowed
+    };
     let (mut ctx, mut event_loop) = ContextBuilder::new("Why cellar is safe", "E")
         .window_mode(
             ggez::conf::WindowMode::default()
-                .dimensions(settings.get_screen_width(), settings.get_screen_height()),
+                .dimensions(settings.get_screen_width(), settings.get_screen_height())
+                .fullscreen_type(fullscreen)
+                .resizable(true),
         )
         .add_resource_path(resource_dir)
         .build()
         .expect("aieee, could not create ggez context!");
 
-    let mut my_game = MyGame::new(settings);
+    let mut my_game = match MyGame::new(&mut ctx, settings, upgraded, map, seed, shop) {
+        Ok(game) => game,
+        Err(e) => {
+            eprintln!("Cannot load images: {}", e);
+            process::exit(1);
+        }
+    };
 
     // Run.
     match event::run(&mut ctx, &mut event_loop, &mut my_game) {
@@ -45,155 +278,499 @@ fn main() {
     }
 }
 
-struct MyGame {
-    settings: gamesettings::GameSettings,
-    p: player::Player,
-    citizens: Vec<citizen::Citizen>,
-    ambulance: ambulance::Ambulance,
+// Upgrade tree with the progress of the player, shown between rounds.
+struct Shop {
+    tree: UpgradeTree,
+    progress: Progress,
+    path: path::PathBuf,
+    // Upgrade picked with the arrows.
+    selected: usize,
+    // Outcome of the last purchase.
+    message: String,
 }
 
-impl MyGame {
-    pub fn new(settings: gamesettings::GameSettings) -> MyGame {
-        // List of random citizens.
-        let mut l = Vec::new();
-        for i in 0..(settings.get_citizens_quan()) {
-            l.push(citizen::random_citizen(
-                settings.get_screen_width(),
-                settings.get_screen_height(),
-                i as usize,
-            ));
+impl Shop {
+    fn save(&mut self) {
+        if let Err(e) = self.progress.save(&self.path) {
+            self.message = e.to_string();
+        }
+    }
+
+    fn buy_selected(&mut self) {
+        if self.selected >= self.tree.get_upgrades().len() {
+            return;
         }
+        match self.tree.buy(self.selected, &mut self.progress) {
+            Ok(()) => {
+                self.message = format!(
+                    "Bought {}",
+                    self.tree.get_upgrades()[self.selected].get_name()
+                );
+                self.save();
+            }
+            Err(reason) => self.message = reason,
+        }
+    }
 
-        MyGame {
-            settings,
-            p: player::init(settings.get_screen_width(), settings.get_screen_height()),
-            citizens: l,
-            ambulance: ambulance::new(
-                settings.get_screen_width(),
-                settings.get_screen_height(),
-                na::Point2::new(400.0, 00.0),
+    fn draw_shop(&self, ctx: &mut Context, screen: (f32, f32), earned: u32) -> GameResult {
+        let background = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::fill(),
+            graphics::Rect::new(0.0, 0.0, screen.0, screen.1),
+            graphics::Color::new(0.0, 0.0, 0.0, 0.8),
+        )?;
+        graphics::draw(ctx, &background, graphics::DrawParam::default())?;
+
+        let mut lines = vec![
+            format!(
+                "Round over, {} points earned. Points: {}",
+                earned,
+                self.progress.get_points()
             ),
+            "Up and Down pick an upgrade, Space buys it, Enter starts the next round.".to_string(),
+            String::new(),
+        ];
+        for (i, upgrade) in self.tree.get_upgrades().iter().enumerate() {
+            let state = if self.progress.owns(upgrade.get_id()) {
+                "bought".to_string()
+            } else if !self.tree.is_unlocked(i, &self.progress) {
+                format!("needs {}", upgrade.get_requires().join(", "))
+            } else {
+                format!("{} points", upgrade.get_cost())
+            };
+            lines.push(format!(
+                "{} {} ({}): {}",
+                if i == self.selected { ">" } else { " " },
+                upgrade.get_name(),
+                state,
+                upgrade.get_description()
+            ));
         }
+        lines.push(String::new());
+        lines.push(self.message.clone());
+
+        for (i, line) in lines.into_iter().enumerate() {
+            let text = graphics::Text::new((line, graphics::Font::default(), 18.0));
+            graphics::draw(
+                ctx,
+                &text,
+                graphics::DrawParam::default().dest(na::Point2::new(40.0, 40.0 + 22.0 * i as f32)),
+            )?;
+        }
+        Ok(())
     }
+}
 
-    fn is_victim(cit: citizen::Citizen, pl: player::Player) -> bool {
-        let player_cent = pl.get_position();
-        let citi_cent = cit.get_position();
-        let dist = na::distance(&player_cent, &citi_cent);
-        dist < (pl.get_radius() + pl.get_sneeze_range())
+struct MyGame {
+    sim: Simulation,
+    // Settings and map without upgrades, to start the next round from.
+    settings: GameSettings,
+    map: Map,
+    shop: Shop,
+    // The round is over and the shop is open, with the points earned in it.
+    round_over: Option<u32>,
+    camera: Camera,
+    // Ground of the map, one batch of tiles for every tileset image.
+    ground: Vec<SpriteBatch>,
+    ambulance_image: graphics::Image,
+    input: Input,
+    // Draw routes of ambulances, toggled with F3.
+    debug: bool,
+    // Show asymptomatic carriers, toggled with F4.
+    reveal: bool,
+}
+
+impl MyGame {
+    pub fn new(
+        ctx: &mut Context,
+        settings: GameSettings,
+        upgraded: GameSettings,
+        map: Map,
+        seed: u64,
+        shop: Shop,
+    ) -> GameResult<MyGame> {
+        let mut ground = Vec::new();
+        for image in map.get_images() {
+            let mut image = graphics::Image::new(ctx, format!("/{}", image.get_path()))?;
+            image.set_filter(graphics::FilterMode::Nearest);
+            ground.push(SpriteBatch::new(image));
+        }
+        for tile in map.get_ground() {
+            ground[tile.image].add(graphics::DrawParam::new().src(tile.src).dest(tile.dest));
+        }
+        let ambulance_image = graphics::Image::new(ctx, "/ambulance2.png")?;
+        Ok(MyGame {
+            camera: camera::new(settings.get_screen_width(), settings.get_screen_height()),
+            sim: Simulation::with_map(upgraded, map.clone(), seed),
+            settings,
+            map,
+            shop,
+            round_over: None,
+            ground,
+            ambulance_image,
+            input: Input::default(),
+            debug: false,
+            reveal: false,
+        })
     }
 
-    fn infection(&mut self) {
-        for cit in self.citizens.iter_mut() {
-            if MyGame::is_victim(*cit, self.p) {
-                cit.become_infected();
-                if cit.needs_doctor() && self.ambulance.is_free() {
-                    cit.stop();
-                    self.p.infect();
-                    self.ambulance.set_destination(
-                        cit.get_id(),
-                        cit.get_position(),
-                        self.settings.get_screen_width(),
-                        self.settings.get_screen_height(),
-                    );
-                }
-            } else {
-                cit.cure();
+    // Points are earned for citizens the player infected.
+    fn end_round(&mut self) {
+        let earned = self.sim.get_player().get_infected().max(0) as u32;
+        self.shop.progress.add_points(earned);
+        self.shop.message.clear();
+        self.shop.save();
+        self.round_over = Some(earned);
+    }
+
+    fn start_round(&mut self) {
+        let effects = self.shop.tree.effects(&self.shop.progress);
+        match self.settings.with_upgrades(effects) {
+            Ok(settings) => {
+                let seed = rand::random();
+                println!("Seed: {}", seed);
+                self.sim = Simulation::with_map(settings, self.map.clone(), seed);
+                self.round_over = None;
             }
+            Err(e) => self.shop.message = e.to_string(),
         }
     }
 }
+
 impl EventHandler for MyGame {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        let swidth = self.settings.get_screen_width();
-        let sheight = self.settings.get_screen_height();
-
-        // Choose citizen randomly and change his angle.
-        while timer::check_update_time(ctx, 1) {
-            let mut rng = rand::thread_rng();
-            let r = rng.gen_range(0, 10);
-            self.citizens[r].change_angle();
-        }
-
-        self.infection();
-        match self.ambulance.get_patient_id() {
-            Some(id) => {
-                if na::distance(
-                    &self.ambulance.get_position(),
-                    &self.citizens[id].get_position(),
-                ) < 2.0 * self.ambulance.get_radius()
-                {
-                    self.citizens[id].go_hospital(self.ambulance.get_position());
-                }
-            }
-            None => (),
+        if self.round_over.is_some() {
+            // Time spent in the shop is not caught up with later.
+            while timer::check_update_time(ctx, TICKS_PER_SECOND) {}
+            return Ok(());
         }
 
-        self.p
-            .move_player(swidth, sheight, input::keyboard::pressed_keys(ctx));
-
-        self.p.sneeze();
+        let pressed_keys = input::keyboard::pressed_keys(ctx);
+        self.input.left = pressed_keys.contains(&KeyCode::Left);
+        self.input.right = pressed_keys.contains(&KeyCode::Right);
+        self.input.up = pressed_keys.contains(&KeyCode::Up);
+        self.input.down = pressed_keys.contains(&KeyCode::Down);
+        let mouse = input::mouse::position(ctx);
+        self.input.aim = Some(self.camera.to_world(na::Point2::new(mouse.x, mouse.y)));
 
-        for cit in self.citizens.iter_mut() {
-            cit.move_being(swidth, swidth);
+        // Run as many fixed steps as needed to catch up with the real time.
+        while timer::check_update_time(ctx, TICKS_PER_SECOND) {
+            self.sim.step(&self.input);
         }
 
-        self.ambulance.move_being(swidth, sheight);
+        let round_time = self.sim.get_settings().get_round_time() as u64;
+        if round_time > 0 && self.sim.get_ticks() >= round_time {
+            self.end_round();
+        }
 
         Ok(())
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
-        graphics::clear(ctx, self.settings.get_bg_col());
+        let settings = self.sim.get_settings();
+        // Part of the step that has already passed, to draw entities between
+        // their previous and current positions.
+        let alpha =
+            (timer::duration_to_f64(timer::remaining_update_time(ctx)) as f32 / DT).min(1.0);
+        let player = self.sim.get_interpolated_player(alpha);
+        graphics::clear(ctx, settings.get_bg_col());
+
+        // The town is drawn in its own coordinates, as seen by the camera.
+        self.camera
+            .follow(player.get_position(), self.sim.get_world_size());
+        let view = self.camera.get_view();
+        graphics::set_screen_coordinates(ctx, view)?;
+
+        for batch in self.ground.iter() {
+            graphics::draw(ctx, batch, graphics::DrawParam::default())?;
+        }
+
+        for obstacle in self.sim.get_map().get_obstacles() {
+            obstacle.draw_obstacle(ctx, settings.get_building_color())?;
+        }
+        for cellar in self.sim.get_cellars() {
+            cellar.draw_cellar(ctx)?;
+        }
+        for hospital in self.sim.get_hospitals() {
+            hospital.draw_hospital(ctx)?;
+        }
+        self.sim.get_authority().draw_authority(ctx)?;
 
         // Player drawing.
-        self.p.draw_player(ctx, self.settings.get_player_col())?;
-        // Citizens drawing.
-        for cit in self.citizens.iter() {
-            cit.draw_citizen(
+        player.draw_player(ctx, settings.get_player_col())?;
+        // Citizens drawing, only the ones that can be seen on the screen.
+        let view_center = na::Point2::new(view.x + view.w / 2.0, view.y + view.h / 2.0);
+        let view_radius = (view.w * view.w + view.h * view.h).sqrt() / 2.0
+            + 2.0 * self.sim.get_max_citizen_radius();
+        for id in self.sim.query_radius(view_center, view_radius) {
+            self.sim.get_interpolated_citizen(id, alpha).draw_citizen(
                 ctx,
-                self.settings.get_disease_color(),
-                self.settings.get_health_col(),
+                settings.get_disease_color(),
+                settings.get_health_col(),
+                self.reveal,
             )?;
         }
 
-        if self.p.check_if_sneezing() {
-            // Draw sneeze range.
-            self.p
-                .draw_sneezing(ctx, self.settings.get_sneeze_color())?;
+        // Draw clouds left by attacks and the current sneeze or cough.
+        self.sim
+            .get_aerosol()
+            .draw_aerosol(ctx, settings.get_sneeze_color(), view)?;
+        player.draw_attack(ctx, settings.get_sneeze_color())?;
+
+        if self.debug {
+            for id in 0..self.sim.get_ambulances().len() {
+                let route = self.sim.get_route(id);
+                if route.len() > 1 {
+                    let line = graphics::Mesh::new_line(
+                        ctx,
+                        &route,
+                        2.0,
+                        graphics::Color::new(1.0, 1.0, 0.0, 0.8),
+                    )?;
+                    graphics::draw(ctx, &line, graphics::DrawParam::default())?;
+                }
+            }
         }
 
-        // Ambulance drawing.
-        self.ambulance.draw_ambulance(ctx)?;
+        // Ambulances drawing.
+        for id in 0..self.sim.get_ambulances().len() {
+            self.sim
+                .get_interpolated_ambulance(id, alpha)
+                .draw_ambulance(ctx, &self.ambulance_image)?;
+        }
+
+        // Minimap and stats are drawn in window coordinates.
+        let (screen_width, screen_height) = self.camera.get_screen();
+        graphics::set_screen_coordinates(
+            ctx,
+            graphics::Rect::new(0.0, 0.0, screen_width, screen_height),
+        )?;
+        minimap::draw_minimap(ctx, &self.sim, settings, view, screen_width, self.reveal)?;
 
         // Draw stats.
+        let stats = self.sim.get_stats();
         let score = graphics::Text::new((
-            format!("Infected: {} ", self.p.get_infected().to_string()),
+            {
+                let round_time = settings.get_round_time() as u64;
+                if round_time > 0 {
+                    format!(
+                        "Infected: {}  Time left: {} s",
+                        self.sim.get_infected(),
+                        round_time.saturating_sub(self.sim.get_ticks()) / TICKS_PER_SECOND as u64
+                    )
+                } else {
+                    format!("Infected: {} ", self.sim.get_infected())
+                }
+            },
             graphics::Font::default(),
             24.0,
         ));
         graphics::draw(ctx, &score, graphics::DrawParam::default())?;
+        // Carriers without symptoms are not counted unless they are revealed.
+        let (exposed, infectious) = if self.reveal {
+            (stats.exposed, stats.infectious)
+        } else {
+            let infected = stats.exposed + stats.infectious;
+            (
+                infected.saturating_sub(stats.asymptomatic + stats.symptomatic),
+                stats.symptomatic,
+            )
+        };
+        let seird = graphics::Text::new((
+            format!(
+                "S: {}  E: {}  I: {}  R: {}  D: {}  Hidden: {}  Strains: {}{}",
+                stats.susceptible,
+                exposed,
+                infectious,
+                stats.recovered,
+                stats.dead,
+                stats.hidden,
+                stats.strains,
+                if self.reveal {
+                    format!("  Asymptomatic: {}", stats.asymptomatic)
+                } else {
+                    String::new()
+                }
+            ),
+            graphics::Font::default(),
+            16.0,
+        ));
+        graphics::draw(
+            ctx,
+            &seird,
+            graphics::DrawParam::default().dest(na::Point2::new(0.0, 26.0)),
+        )?;
+        let queue = graphics::Text::new((
+            format!(
+                "Waiting for ambulance: {}  Average wait: {:.1} s  Beds: {}/{}{}",
+                stats.waiting,
+                stats.average_wait / TICKS_PER_SECOND as f32,
+                stats.occupied_beds,
+                stats.beds,
+                if stats.overflow {
+                    "  HOSPITAL FULL"
+                } else {
+                    ""
+                }
+            ),
+            graphics::Font::default(),
+            16.0,
+        ));
+        graphics::draw(
+            ctx,
+            &queue,
+            graphics::DrawParam::default().dest(na::Point2::new(0.0, 44.0)),
+        )?;
+
+        // Stamina bar with the charge of the attack under it.
+        let sim_player = self.sim.get_player();
+        let stamina = if sim_player.get_max_stamina() > 0.0 {
+            sim_player.get_stamina() / sim_player.get_max_stamina()
+        } else {
+            0.0
+        };
+        let mut bars = graphics::MeshBuilder::new();
+        bars.rectangle(
+            graphics::DrawMode::fill(),
+            graphics::Rect::new(0.0, 64.0, STAMINA_BAR_WIDTH, 10.0),
+            graphics::Color::new(0.0, 0.0, 0.0, 0.5),
+        );
+        bars.rectangle(
+            graphics::DrawMode::fill(),
+            graphics::Rect::new(0.0, 64.0, STAMINA_BAR_WIDTH * stamina, 10.0),
+            if sim_player.is_exhausted() {
+                graphics::Color::new(0.6, 0.3, 0.3, 1.0)
+            } else {
+                graphics::Color::new(0.3, 0.8, 1.0, 1.0)
+            },
+        );
+        if sim_player.get_charge() > 0.0 {
+            bars.rectangle(
+                graphics::DrawMode::fill(),
+                graphics::Rect::new(0.0, 76.0, STAMINA_BAR_WIDTH * sim_player.get_charge(), 4.0),
+                graphics::Color::new(1.0, 0.9, 0.2, 1.0),
+            );
+        }
+        let bars = bars.build(ctx)?;
+        graphics::draw(ctx, &bars, graphics::DrawParam::default())?;
+        let cooldown = sim_player.get_cooldown();
+        let stamina_text = graphics::Text::new((
+            if cooldown > 0 {
+                format!(
+                    "Stamina  Cooldown: {:.1} s",
+                    cooldown as f32 / TICKS_PER_SECOND as f32
+                )
+            } else {
+                "Stamina".to_string()
+            },
+            graphics::Font::default(),
+            16.0,
+        ));
+        graphics::draw(
+            ctx,
+            &stamina_text,
+            graphics::DrawParam::default().dest(na::Point2::new(STAMINA_BAR_WIDTH + 8.0, 62.0)),
+        )?;
+
+        // Countermeasures in force.
+        let authority = self.sim.get_authority();
+        let mut measures = format!(
+            "Health authority ({:?}): budget {:.0}",
+            authority.get_params().get_strategy(),
+            authority.get_budget()
+        );
+        let seconds = |measure| authority.get_remaining(measure) / TICKS_PER_SECOND;
+        for (name, measure) in [
+            ("Masks", Measure::Masks),
+            ("Lockdown", Measure::Lockdown),
+            ("Vaccination", Measure::Vaccination),
+        ]
+        .iter()
+        {
+            if authority.get_remaining(*measure) > 0 {
+                measures.push_str(&format!("  {} {} s", name, seconds(*measure)));
+            }
+        }
+        if !authority.get_stations().is_empty() {
+            measures.push_str(&format!("  Testing: {}", authority.get_stations().len()));
+        }
+        if !authority.get_closures().is_empty() {
+            measures.push_str(&format!("  Closed: {}", authority.get_closures().len()));
+        }
+        if sim_player.is_held() {
+            measures.push_str("  CAUGHT");
+        }
+        let measures = graphics::Text::new((measures, graphics::Font::default(), 16.0));
+        graphics::draw(
+            ctx,
+            &measures,
+            graphics::DrawParam::default().dest(na::Point2::new(0.0, 84.0)),
+        )?;
+
+        if let Some(earned) = self.round_over {
+            self.shop
+                .draw_shop(ctx, (screen_width, screen_height), earned)?;
+        }
 
         graphics::present(ctx)
     }
 
-    fn mouse_button_down_event(
+    fn key_down_event(
         &mut self,
-        _ctx: &mut Context,
-        _button: MouseButton,
-        _x: f32,
-        _y: f32,
+        ctx: &mut Context,
+        keycode: KeyCode,
+        _keymods: KeyMods,
+        _repeat: bool,
     ) {
-        self.p.set_sneeze(true);
+        if self.round_over.is_some() {
+            let count = self.shop.tree.get_upgrades().len();
+            match keycode {
+                KeyCode::Up if self.shop.selected > 0 => self.shop.selected -= 1,
+                KeyCode::Down if self.shop.selected + 1 < count => self.shop.selected += 1,
+                KeyCode::Space => self.shop.buy_selected(),
+                KeyCode::Return => self.start_round(),
+                KeyCode::Escape => event::quit(ctx),
+                _ => (),
+            }
+            return;
+        }
+        match keycode {
+            KeyCode::Escape => event::quit(ctx),
+            KeyCode::F3 => self.debug = !self.debug,
+            KeyCode::F4 => self.reveal = !self.reveal,
+            KeyCode::Equals | KeyCode::Add => self.camera.zoom_by(1.0),
+            KeyCode::Minus | KeyCode::Subtract => self.camera.zoom_by(-1.0),
+            _ => (),
+        }
     }
 
-    fn mouse_button_up_event(
+    fn mouse_wheel_event(&mut self, _ctx: &mut Context, _x: f32, y: f32) {
+        self.camera.zoom_by(y);
+    }
+
+    fn resize_event(&mut self, _ctx: &mut Context, width: f32, height: f32) {
+        self.camera.resize(width, height);
+    }
+
+    fn mouse_button_down_event(
         &mut self,
         _ctx: &mut Context,
-        _button: MouseButton,
+        button: MouseButton,
         _x: f32,
         _y: f32,
     ) {
-        self.p.set_sneeze(false);
+        match button {
+            MouseButton::Left => self.input.sneeze = true,
+            MouseButton::Right => self.input.cough = true,
+            _ => (),
+        }
+    }
+
+    fn mouse_button_up_event(&mut self, _ctx: &mut Context, button: MouseButton, _x: f32, _y: f32) {
+        match button {
+            MouseButton::Left => self.input.sneeze = false,
+            MouseButton::Right => self.input.cough = false,
+            _ => (),
+        }
     }
 }
@@ -0,0 +1,649 @@
+use ggez::nalgebra as na;
+use ggez::{graphics, Context, GameResult};
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::tiled::{self, Shape, TiledMap};
+
+// Number of points of polygons standing for ellipses of Tiled maps.
+const ELLIPSE_POINTS: usize = 16;
+
+#[derive(Debug)]
+pub enum MapError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Xml(roxmltree::Error),
+    Json(serde_json::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapError::Io(e) => write!(f, "Cannot read map: {}", e),
+            MapError::Parse(e) => write!(f, "Cannot parse map: {}", e),
+            MapError::Xml(e) => write!(f, "Cannot parse map: {}", e),
+            MapError::Json(e) => write!(f, "Cannot parse map: {}", e),
+            MapError::Invalid(reason) => write!(f, "Invalid map: {}", reason),
+        }
+    }
+}
+
+impl Error for MapError {}
+
+impl From<io::Error> for MapError {
+    fn from(e: io::Error) -> Self {
+        MapError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for MapError {
+    fn from(e: toml::de::Error) -> Self {
+        MapError::Parse(e)
+    }
+}
+
+impl From<roxmltree::Error> for MapError {
+    fn from(e: roxmltree::Error) -> Self {
+        MapError::Xml(e)
+    }
+}
+
+impl From<serde_json::Error> for MapError {
+    fn from(e: serde_json::Error) -> Self {
+        MapError::Json(e)
+    }
+}
+
+// Obstacles are written either as rectangles, `{ x = 100.0, y = 50.0, width = 80.0, height = 120.0 }`,
+// or as polygons, `{ points = [[0.0, 0.0], [50.0, 0.0], [0.0, 50.0]] }`.
+#[derive(Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum ObstacleDef {
+    Rect {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    },
+    Polygon {
+        points: Vec<[f32; 2]>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MapDef {
+    #[serde(default)]
+    obstacles: Vec<ObstacleDef>,
+}
+
+// Building or wall nobody can walk through.
+#[derive(Clone)]
+pub struct Obstacle {
+    points: Vec<na::Point2<f32>>,
+}
+
+impl Obstacle {
+    pub fn rect(x: f32, y: f32, width: f32, height: f32) -> Obstacle {
+        Obstacle {
+            points: vec![
+                na::Point2::new(x, y),
+                na::Point2::new(x + width, y),
+                na::Point2::new(x + width, y + height),
+                na::Point2::new(x, y + height),
+            ],
+        }
+    }
+
+    pub fn polygon(points: Vec<na::Point2<f32>>) -> Obstacle {
+        Obstacle { points }
+    }
+
+    pub fn get_points(&self) -> &[na::Point2<f32>] {
+        &self.points
+    }
+
+    fn edges<'a>(&'a self) -> impl Iterator<Item = (na::Point2<f32>, na::Point2<f32>)> + 'a {
+        self.points
+            .iter()
+            .zip(self.points.iter().cycle().skip(1))
+            .map(|(a, b)| (*a, *b))
+    }
+
+    pub fn contains(&self, p: na::Point2<f32>) -> bool {
+        let mut inside = false;
+        for (a, b) in self.edges() {
+            if (a.y > p.y) != (b.y > p.y) && p.x < a.x + (p.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+                inside = !inside;
+            }
+        }
+        inside
+    }
+
+    fn closest_point(&self, p: na::Point2<f32>) -> na::Point2<f32> {
+        self.edges()
+            .map(|(a, b)| closest_on_segment(a, b, p))
+            .fold(None, |best: Option<na::Point2<f32>>, q| match best {
+                Some(b) if na::distance(&b, &p) <= na::distance(&q, &p) => Some(b),
+                _ => Some(q),
+            })
+            .unwrap_or(p)
+    }
+
+    // New center for a circle overlapping the obstacle, moved out of it along
+    // the normal of the closest edge, together with that normal.
+    pub fn push_out(
+        &self,
+        center: na::Point2<f32>,
+        radius: f32,
+    ) -> Option<(na::Point2<f32>, na::Vector2<f32>)> {
+        let closest = self.closest_point(center);
+        let offset = center - closest;
+        let dist = offset.norm();
+        if self.contains(center) {
+            let normal = if dist > 0.0 {
+                -offset / dist
+            } else {
+                na::Vector2::new(0.0, -1.0)
+            };
+            Some((closest + normal * radius, normal))
+        } else if dist < radius && dist > 0.0 {
+            let normal = offset / dist;
+            Some((closest + normal * radius, normal))
+        } else {
+            None
+        }
+    }
+
+    pub fn intersects_segment(&self, p: na::Point2<f32>, q: na::Point2<f32>) -> bool {
+        self.contains(p) || self.edges().any(|(a, b)| segments_intersect(a, b, p, q))
+    }
+
+    pub fn draw_obstacle(&self, ctx: &mut Context, color: graphics::Color) -> GameResult {
+        let polygon =
+            graphics::Mesh::new_polygon(ctx, graphics::DrawMode::fill(), &self.points, color)?;
+        graphics::draw(ctx, &polygon, graphics::DrawParam::default())
+    }
+}
+
+fn closest_on_segment(
+    a: na::Point2<f32>,
+    b: na::Point2<f32>,
+    p: na::Point2<f32>,
+) -> na::Point2<f32> {
+    let ab = b - a;
+    let len = ab.norm_squared();
+    if len == 0.0 {
+        return a;
+    }
+    let t = ((p - a).dot(&ab) / len).clamp(0.0, 1.0);
+    a + ab * t
+}
+
+fn cross(o: na::Point2<f32>, a: na::Point2<f32>, b: na::Point2<f32>) -> f32 {
+    (a.x - o.x) * (b.y - o.y) - (a.y - o.y) * (b.x - o.x)
+}
+
+fn segments_intersect(
+    a: na::Point2<f32>,
+    b: na::Point2<f32>,
+    c: na::Point2<f32>,
+    d: na::Point2<f32>,
+) -> bool {
+    let d1 = cross(c, d, a);
+    let d2 = cross(c, d, b);
+    let d3 = cross(a, b, c);
+    let d4 = cross(a, b, d);
+    ((d1 > 0.0 && d2 < 0.0) || (d1 < 0.0 && d2 > 0.0))
+        && ((d3 > 0.0 && d4 < 0.0) || (d3 < 0.0 && d4 > 0.0))
+}
+
+// Place of a hospital or a cellar, given by its center and the side of its square.
+#[derive(Copy, Clone)]
+pub struct Site {
+    position: na::Point2<f32>,
+    size: f32,
+}
+
+impl Site {
+    pub fn get_position(self) -> na::Point2<f32> {
+        self.position
+    }
+
+    pub fn get_size(self) -> f32 {
+        self.size
+    }
+}
+
+// Image the ground tiles are cut from, looked up in the `resources` directory.
+#[derive(Clone)]
+pub struct TileImage {
+    path: String,
+}
+
+impl TileImage {
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+}
+
+// Single tile of the ground. `src` is the part of the image in fractions of its size.
+#[derive(Copy, Clone)]
+pub struct Tile {
+    pub image: usize,
+    pub src: graphics::Rect,
+    pub dest: na::Point2<f32>,
+}
+
+// Static layout of the town. The default map is empty and has no size of its
+// own, the town then takes the whole screen.
+#[derive(Clone, Default)]
+pub struct Map {
+    size: Option<(f32, f32)>,
+    obstacles: Vec<Obstacle>,
+    hospitals: Vec<Site>,
+    cellars: Vec<Site>,
+    player_spawn: Option<na::Point2<f32>>,
+    citizen_spawns: Vec<graphics::Rect>,
+    depots: Vec<na::Point2<f32>>,
+    images: Vec<TileImage>,
+    ground: Vec<Tile>,
+}
+
+impl Map {
+    pub fn new(obstacles: Vec<Obstacle>) -> Map {
+        Map {
+            obstacles,
+            ..Map::default()
+        }
+    }
+
+    // Maps are read from TOML files with obstacles only, or from Tiled maps
+    // saved as TMX or JSON.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Map, MapError> {
+        let content = fs::read_to_string(&path)?;
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("tmx") => Map::from_tiled(tiled::from_tmx(&content)?),
+            Some("json") => Map::from_tiled(tiled::from_json(&content)?),
+            _ => Map::from_toml(&content),
+        }
+    }
+    pub fn from_toml(content: &str) -> Result<Map, MapError> {
+        let def: MapDef = toml::from_str(content)?;
+        let mut obstacles = Vec::new();
+        for obstacle in def.obstacles {
+            obstacles.push(match obstacle {
+                ObstacleDef::Rect {
+                    x,
+                    y,
+                    width,
+                    height,
+                } => {
+                    if width <= 0.0 || height <= 0.0 {
+                        return Err(MapError::Invalid(
+                            "rectangles must have positive width and height".to_string(),
+                        ));
+                    }
+                    Obstacle::rect(x, y, width, height)
+                }
+                ObstacleDef::Polygon { points } => {
+                    if points.len() < 3 {
+                        return Err(MapError::Invalid(
+                            "polygons must have at least 3 points".to_string(),
+                        ));
+                    }
+                    Obstacle::polygon(points.iter().map(|p| na::Point2::new(p[0], p[1])).collect())
+                }
+            });
+        }
+        Ok(Map::new(obstacles))
+    }
+
+    // Objects of the map are told apart by their type: `obstacle`, `hospital`,
+    // `cellar`, `player_spawn`, `citizen_spawn` or `depot`. Objects without a
+    // type in a layer named `obstacles` are obstacles.
+    pub fn from_tiled(tiled: TiledMap) -> Result<Map, MapError> {
+        let invalid = |reason: &str| Err(MapError::Invalid(reason.to_string()));
+        if tiled.width == 0
+            || tiled.height == 0
+            || tiled.tile_width <= 0.0
+            || tiled.tile_height <= 0.0
+        {
+            return invalid("map and tiles must have positive size");
+        }
+        let mut map = Map {
+            size: Some((
+                tiled.width as f32 * tiled.tile_width,
+                tiled.height as f32 * tiled.tile_height,
+            )),
+            ..Map::default()
+        };
+
+        for object in tiled.objects.iter() {
+            let center = na::Point2::new(
+                object.x + object.width / 2.0,
+                object.y + object.height / 2.0,
+            );
+            let kind = if object.kind.is_empty() && object.layer == "obstacles" {
+                "obstacle"
+            } else {
+                object.kind.as_str()
+            };
+            match kind {
+                "obstacle" => map.obstacles.push(match &object.shape {
+                    Shape::Rect if object.width > 0.0 && object.height > 0.0 => {
+                        Obstacle::rect(object.x, object.y, object.width, object.height)
+                    }
+                    Shape::Ellipse if object.width > 0.0 && object.height > 0.0 => {
+                        Obstacle::polygon(
+                            (0..ELLIPSE_POINTS)
+                                .map(|i| {
+                                    let angle = i as f32 / ELLIPSE_POINTS as f32
+                                        * 2.0
+                                        * std::f32::consts::PI;
+                                    center
+                                        + na::Vector2::new(
+                                            angle.cos() * object.width / 2.0,
+                                            angle.sin() * object.height / 2.0,
+                                        )
+                                })
+                                .collect(),
+                        )
+                    }
+                    Shape::Polygon(points) if points.len() >= 3 => Obstacle::polygon(
+                        points
+                            .iter()
+                            .map(|p| na::Point2::new(object.x + p.x, object.y + p.y))
+                            .collect(),
+                    ),
+                    Shape::Polygon(_) => return invalid("polygons must have at least 3 points"),
+                    _ => {
+                        return invalid(
+                            "obstacles must be rectangles, ellipses or polygons of positive size",
+                        )
+                    }
+                }),
+                "hospital" | "cellar" => {
+                    if object.width <= 0.0 || object.height <= 0.0 {
+                        return invalid(
+                            "hospitals and cellars must be rectangles of positive size",
+                        );
+                    }
+                    let site = Site {
+                        position: center,
+                        size: object.width.max(object.height),
+                    };
+                    if kind == "hospital" {
+                        map.hospitals.push(site);
+                    } else {
+                        map.cellars.push(site);
+                    }
+                }
+                "player_spawn" => map.player_spawn = Some(center),
+                "citizen_spawn" => {
+                    if object.width <= 0.0 || object.height <= 0.0 {
+                        return invalid("citizen spawn areas must be rectangles of positive size");
+                    }
+                    map.citizen_spawns.push(graphics::Rect::new(
+                        object.x,
+                        object.y,
+                        object.width,
+                        object.height,
+                    ));
+                }
+                "depot" => map.depots.push(center),
+                "" => return invalid("objects must have a type"),
+                other => {
+                    return Err(MapError::Invalid(format!(
+                        "unknown object type `{}`",
+                        other
+                    )))
+                }
+            }
+        }
+
+        // Tiles are aligned with the bottom left corner of their cell, like in Tiled.
+        for layer in tiled.layers.iter() {
+            for (i, &gid) in layer.data.iter().enumerate() {
+                if gid == 0 {
+                    continue;
+                }
+                let set = match tiled.tilesets.iter().rposition(|t| t.first_gid <= gid) {
+                    Some(set) => set,
+                    None => return invalid("layer uses a tile missing from the tilesets"),
+                };
+                let tileset = &tiled.tilesets[set];
+                if tileset.columns == 0 || tileset.image_width <= 0.0 || tileset.image_height <= 0.0
+                {
+                    return invalid("tilesets must have columns and an image of positive size");
+                }
+                let local = gid - tileset.first_gid;
+                let col = (local % tileset.columns) as f32;
+                let row = (local / tileset.columns) as f32;
+                let cols = layer.width.max(1) as usize;
+                map.ground.push(Tile {
+                    image: set,
+                    src: graphics::Rect::new(
+                        (tileset.margin + col * (tileset.tile_width + tileset.spacing))
+                            / tileset.image_width,
+                        (tileset.margin + row * (tileset.tile_height + tileset.spacing))
+                            / tileset.image_height,
+                        tileset.tile_width / tileset.image_width,
+                        tileset.tile_height / tileset.image_height,
+                    ),
+                    dest: na::Point2::new(
+                        (i % cols) as f32 * tiled.tile_width,
+                        (i / cols) as f32 * tiled.tile_height + tiled.tile_height
+                            - tileset.tile_height,
+                    ),
+                });
+            }
+        }
+        map.images = tiled
+            .tilesets
+            .iter()
+            .map(|t| TileImage {
+                path: t.image.clone(),
+            })
+            .collect();
+        Ok(map)
+    }
+
+    pub fn get_size(&self) -> Option<(f32, f32)> {
+        self.size
+    }
+
+    pub fn get_hospitals(&self) -> &[Site] {
+        &self.hospitals
+    }
+
+    pub fn get_cellars(&self) -> &[Site] {
+        &self.cellars
+    }
+
+    pub fn get_player_spawn(&self) -> Option<na::Point2<f32>> {
+        self.player_spawn
+    }
+
+    pub fn get_citizen_spawns(&self) -> &[graphics::Rect] {
+        &self.citizen_spawns
+    }
+
+    pub fn get_depots(&self) -> &[na::Point2<f32>] {
+        &self.depots
+    }
+
+    pub fn get_images(&self) -> &[TileImage] {
+        &self.images
+    }
+
+    pub fn get_ground(&self) -> &[Tile] {
+        &self.ground
+    }
+
+    pub fn get_obstacles(&self) -> &[Obstacle] {
+        &self.obstacles
+    }
+
+    pub fn is_blocked(&self, p: na::Point2<f32>, radius: f32) -> bool {
+        self.obstacles
+            .iter()
+            .any(|o| o.push_out(p, radius).is_some())
+    }
+
+    // Moves a circle out of all obstacles it overlaps. Returns the new center
+    // and the normal of the last obstacle hit, if any.
+    pub fn collide(
+        &self,
+        center: na::Point2<f32>,
+        radius: f32,
+    ) -> (na::Point2<f32>, Option<na::Vector2<f32>>) {
+        let mut center = center;
+        let mut hit = None;
+        for obstacle in self.obstacles.iter() {
+            if let Some((c, normal)) = obstacle.push_out(center, radius) {
+                center = c;
+                hit = Some(normal);
+            }
+        }
+        (center, hit)
+    }
+
+    // True if there is no obstacle between the two points.
+    pub fn is_visible(&self, from: na::Point2<f32>, to: na::Point2<f32>) -> bool {
+        !self
+            .obstacles
+            .iter()
+            .any(|o| o.intersects_segment(from, to))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Part of `resources/town.tmx` saved as JSON.
+    const TOWN_JSON: &str = r#"{
+        "orientation": "orthogonal",
+        "width": 20,
+        "height": 15,
+        "tilewidth": 40,
+        "tileheight": 40,
+        "tilesets": [{
+            "firstgid": 1,
+            "image": "tiles.png",
+            "imagewidth": 80,
+            "imageheight": 80,
+            "tilewidth": 40,
+            "tileheight": 40,
+            "columns": 2
+        }],
+        "layers": [
+            {
+                "type": "tilelayer",
+                "name": "ground",
+                "width": 2,
+                "height": 1,
+                "data": [2, 0]
+            },
+            {
+                "type": "objectgroup",
+                "name": "obstacles",
+                "objects": [
+                    { "x": 60, "y": 60, "width": 160, "height": 100 },
+                    {
+                        "x": 420,
+                        "y": 240,
+                        "polygon": [
+                            { "x": 0, "y": 0 },
+                            { "x": 120, "y": -20 },
+                            { "x": 140, "y": 100 },
+                            { "x": 20, "y": 120 }
+                        ]
+                    }
+                ]
+            },
+            {
+                "type": "objectgroup",
+                "name": "places",
+                "objects": [
+                    { "type": "hospital", "x": 660, "y": 460, "width": 80, "height": 80 },
+                    { "class": "cellar", "x": 180, "y": 190, "width": 40, "height": 40 },
+                    { "type": "player_spawn", "x": 400, "y": 180, "point": true },
+                    { "type": "depot", "x": 620, "y": 560, "point": true },
+                    { "type": "citizen_spawn", "x": 20, "y": 440, "width": 200, "height": 140 }
+                ]
+            }
+        ]
+    }"#;
+
+    fn town() -> Map {
+        Map::from_file(concat!(env!("CARGO_MANIFEST_DIR"), "/resources/town.tmx")).unwrap()
+    }
+
+    #[test]
+    fn reads_town_from_tmx() {
+        let map = town();
+        assert_eq!(map.get_size(), Some((800.0, 600.0)));
+
+        let obstacles = map.get_obstacles();
+        assert_eq!(obstacles.len(), 8);
+        assert!(obstacles[0].contains(na::Point2::new(140.0, 110.0)));
+        assert!(!obstacles[0].contains(na::Point2::new(40.0, 40.0)));
+        // Points of polygons are relative to the object.
+        assert_eq!(obstacles[6].get_points()[1], na::Point2::new(540.0, 220.0));
+
+        assert_eq!(map.get_hospitals().len(), 1);
+        assert_eq!(
+            map.get_hospitals()[0].get_position(),
+            na::Point2::new(700.0, 500.0)
+        );
+        assert_eq!(map.get_hospitals()[0].get_size(), 80.0);
+        assert_eq!(map.get_cellars().len(), 3);
+        assert_eq!(map.get_player_spawn(), Some(na::Point2::new(400.0, 180.0)));
+        assert_eq!(map.get_depots(), &[na::Point2::new(620.0, 560.0)][..]);
+        assert_eq!(map.get_citizen_spawns().len(), 3);
+        assert_eq!(
+            map.get_citizen_spawns()[0],
+            graphics::Rect::new(20.0, 440.0, 200.0, 140.0)
+        );
+
+        assert_eq!(map.get_images().len(), 1);
+        assert_eq!(map.get_images()[0].get_path(), "tiles.png");
+        assert_eq!(map.get_ground().len(), 20 * 15);
+    }
+
+    #[test]
+    fn json_gives_the_same_town() {
+        let town = town();
+        let map = Map::from_tiled(tiled::from_json(TOWN_JSON).unwrap()).unwrap();
+        assert_eq!(map.get_size(), town.get_size());
+        assert_eq!(map.get_obstacles().len(), 2);
+        assert_eq!(
+            map.get_obstacles()[0].get_points(),
+            town.get_obstacles()[0].get_points()
+        );
+        assert_eq!(
+            map.get_obstacles()[1].get_points(),
+            town.get_obstacles()[6].get_points()
+        );
+        assert_eq!(
+            map.get_hospitals()[0].get_position(),
+            town.get_hospitals()[0].get_position()
+        );
+        assert_eq!(
+            map.get_cellars()[0].get_position(),
+            town.get_cellars()[0].get_position()
+        );
+        assert_eq!(map.get_player_spawn(), town.get_player_spawn());
+        assert_eq!(map.get_depots(), town.get_depots());
+        assert_eq!(map.get_citizen_spawns()[0], town.get_citizen_spawns()[0]);
+        assert_eq!(map.get_ground().len(), 1);
+        assert_eq!(map.get_ground()[0].dest, town.get_ground()[0].dest);
+    }
+}
@@ -0,0 +1,109 @@
+use ggez::nalgebra as na;
+use ggez::{graphics, Context, GameResult};
+
+use crate::gamesettings::GameSettings;
+use crate::moveable::Moveable;
+use crate::simulation::Simulation;
+
+// Length of the longer side of the minimap and its distance from the edges of the window, in pixels.
+const SIZE: f32 = 160.0;
+const MARGIN: f32 = 10.0;
+
+// Whole town drawn small in the top right corner of the window, with the part
+// shown by the camera outlined. Drawn in screen coordinates, `reveal` shows
+// asymptomatic carriers.
+pub fn draw_minimap(
+    ctx: &mut Context,
+    sim: &Simulation,
+    settings: GameSettings,
+    view: graphics::Rect,
+    screen_width: f32,
+    reveal: bool,
+) -> GameResult {
+    let (width, height) = sim.get_world_size();
+    let scale = SIZE / width.max(height);
+    let origin = na::Point2::new(screen_width - MARGIN - width * scale, MARGIN);
+    let to_map = |p: na::Point2<f32>| origin + p.coords * scale;
+
+    let mut mesh = graphics::MeshBuilder::new();
+    mesh.rectangle(
+        graphics::DrawMode::fill(),
+        graphics::Rect::new(origin.x, origin.y, width * scale, height * scale),
+        graphics::Color::new(0.0, 0.0, 0.0, 0.6),
+    );
+    for obstacle in sim.get_map().get_obstacles() {
+        let points: Vec<na::Point2<f32>> =
+            obstacle.get_points().iter().map(|p| to_map(*p)).collect();
+        mesh.polygon(
+            graphics::DrawMode::fill(),
+            &points,
+            settings.get_building_color(),
+        )?;
+    }
+    for cellar in sim.get_cellars() {
+        mesh.circle(
+            graphics::DrawMode::fill(),
+            to_map(cellar.get_position()),
+            2.0,
+            0.5,
+            graphics::Color::new(0.35, 0.22, 0.1, 1.0),
+        );
+    }
+    for hospital in sim.get_hospitals() {
+        mesh.circle(
+            graphics::DrawMode::fill(),
+            to_map(hospital.get_position()),
+            3.0,
+            0.5,
+            graphics::WHITE,
+        );
+    }
+    for cit in sim.get_citizens().iter().filter(|c| !c.is_hidden()) {
+        let p = to_map(cit.get_position());
+        mesh.rectangle(
+            graphics::DrawMode::fill(),
+            graphics::Rect::new(p.x - 0.5, p.y - 0.5, 1.0, 1.0),
+            cit.get_color(
+                settings.get_disease_color(),
+                settings.get_health_col(),
+                reveal,
+            ),
+        );
+    }
+    for amb in sim.get_ambulances() {
+        mesh.circle(
+            graphics::DrawMode::fill(),
+            to_map(amb.get_position()),
+            1.5,
+            0.5,
+            graphics::Color::new(1.0, 0.2, 0.2, 1.0),
+        );
+    }
+    mesh.circle(
+        graphics::DrawMode::fill(),
+        to_map(sim.get_player().get_position()),
+        2.5,
+        0.5,
+        settings.get_player_col(),
+    );
+
+    // Part of the town in the window, cut to the edges of the minimap.
+    let left = view.x.max(0.0);
+    let top = view.y.max(0.0);
+    let right = (view.x + view.w).min(width);
+    let bottom = (view.y + view.h).min(height);
+    let corner = to_map(na::Point2::new(left, top));
+    mesh.rectangle(
+        graphics::DrawMode::stroke(1.0),
+        graphics::Rect::new(
+            corner.x,
+            corner.y,
+            (right - left) * scale,
+            (bottom - top) * scale,
+        ),
+        graphics::WHITE,
+    );
+
+    let mesh = mesh.build(ctx)?;
+    graphics::draw(ctx, &mesh, graphics::DrawParam::default())
+}
@@ -3,12 +3,72 @@ use rand::{
     distributions::{Distribution, Standard},
     Rng,
 };
+use serde::{Deserialize, Serialize};
 
 pub trait Moveable {
     fn get_position(&self) -> na::Point2<f32>;
     fn get_radius(self) -> f32;
     fn get_speed(self) -> f32;
-    fn move_being(&mut self, screen_width: f32, screen_height: f32);
+    fn set_position(&mut self, p: na::Point2<f32>);
+    // Moves the being by the distance it covers in `dt` seconds and keeps it
+    // in the town of the given size as the boundary policy says.
+    fn move_being<R: Rng + ?Sized>(
+        &mut self,
+        boundary: Boundary,
+        world: (f32, f32),
+        dt: f32,
+        rng: &mut R,
+    );
+}
+
+// What happens to a being that reaches the edge of the town.
+#[derive(Copy, Clone, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Boundary {
+    // Leaves the town and comes back on the opposite side.
+    Wrap,
+    // Turns back at the edge. The player stops like with `Clamp`, as the
+    // controls set its velocity again every step.
+    Bounce,
+    // Stops at the edge.
+    Clamp,
+    // Leaves the town and comes back at a random place of it.
+    Respawn,
+    // Wraps like `Wrap`, and citizens come back healthy.
+    Recover,
+}
+
+// Boundary policy of each kind of beings.
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Boundaries {
+    player: Boundary,
+    citizens: Boundary,
+    ambulances: Boundary,
+}
+
+impl Boundaries {
+    pub fn get_player(self) -> Boundary {
+        self.player
+    }
+
+    pub fn get_citizens(self) -> Boundary {
+        self.citizens
+    }
+
+    pub fn get_ambulances(self) -> Boundary {
+        self.ambulances
+    }
+}
+
+impl Default for Boundaries {
+    fn default() -> Self {
+        Boundaries {
+            player: Boundary::Wrap,
+            citizens: Boundary::Recover,
+            ambulances: Boundary::Clamp,
+        }
+    }
 }
 
 #[derive(PartialEq)]
@@ -52,6 +112,10 @@ impl EntityParams {
         self.speed
     }
 
+    pub fn get_velocity(self) -> na::Vector2<f32> {
+        self.velocity
+    }
+
     pub fn set_velocity(&mut self, v: na::Vector2<f32>) {
         self.velocity = v;
     }
@@ -91,22 +155,82 @@ impl EntityParams {
         Zone::Inside
     }
 
-    pub fn move_step(&mut self) {
-        self.center += self.speed * self.velocity
+    // Applies the boundary policy, returns true if the being has left the town
+    // or hit its edge.
+    pub fn keep_inside<R: Rng + ?Sized>(
+        &mut self,
+        boundary: Boundary,
+        (width, height): (f32, f32),
+        rng: &mut R,
+    ) -> bool {
+        let r = self.radius;
+        match boundary {
+            Boundary::Wrap | Boundary::Recover => match self.where_is(width, height) {
+                Zone::LeftBorder => self.set_cx(width + r),
+                Zone::RightBorder => self.set_cx(-r),
+                Zone::BottomBorder => self.set_cy(-r),
+                Zone::UpBorder => self.set_cy(height + r),
+                Zone::Inside => return false,
+            },
+            Boundary::Respawn => {
+                if self.where_is(width, height) == Zone::Inside {
+                    return false;
+                }
+                self.center =
+                    na::Point2::new(rng.gen_range(0.0, width), rng.gen_range(0.0, height));
+            }
+            Boundary::Bounce | Boundary::Clamp => {
+                // Towns narrower than the being keep it in the middle.
+                let x = self
+                    .center
+                    .x
+                    .clamp(r.min(width / 2.0), (width - r).max(width / 2.0));
+                let y = self
+                    .center
+                    .y
+                    .clamp(r.min(height / 2.0), (height - r).max(height / 2.0));
+                if x == self.center.x && y == self.center.y {
+                    return false;
+                }
+                if boundary == Boundary::Bounce {
+                    if x != self.center.x {
+                        self.velocity.x = -self.velocity.x;
+                    }
+                    if y != self.center.y {
+                        self.velocity.y = -self.velocity.y;
+                    }
+                }
+                self.center = na::Point2::new(x, y);
+            }
+        }
+        true
+    }
+
+    pub fn set_center(&mut self, c: na::Point2<f32>) {
+        self.center = c;
+    }
+
+    // Speed is given in units per second.
+    pub fn move_step(&mut self, dt: f32) {
+        self.center += self.speed * dt * self.velocity
     }
 
     pub fn stop(&mut self) {
         self.speed = 0.0;
     }
 
-    pub fn random((width, height): (f32, f32)) -> EntityParams {
-        let mut rng = rand::thread_rng();
+    pub fn random<R: Rng + ?Sized>(
+        (width, height): (f32, f32),
+        radius: f32,
+        speed: f32,
+        rng: &mut R,
+    ) -> EntityParams {
         let angle: f64 = rng.gen_range(0.0, 360.0);
         EntityParams {
             center: na::Point2::new(rng.gen_range(0.0, width), rng.gen_range(0.0, height)),
-            radius: 20.0,
+            radius,
             velocity: na::Vector2::new(angle.sin() as f32, angle.cos() as f32),
-            speed: 5.0,
+            speed,
         }
     }
 
@@ -119,3 +243,110 @@ impl EntityParams {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    const WORLD: (f32, f32) = (100.0, 80.0);
+    const RADIUS: f32 = 5.0;
+
+    // Beings which have just left the town over the left, right, top and
+    // bottom edge, each moving away from it.
+    fn crossings() -> Vec<EntityParams> {
+        [
+            ((-6.0, 40.0), (-1.0, 0.0)),
+            ((106.0, 40.0), (1.0, 0.0)),
+            ((50.0, -6.0), (0.0, -1.0)),
+            ((50.0, 86.0), (0.0, 1.0)),
+        ]
+        .iter()
+        .map(|&((x, y), (vx, vy))| {
+            EntityParams::new(
+                na::Point2::new(x, y),
+                RADIUS,
+                na::Vector2::new(vx, vy),
+                10.0,
+            )
+        })
+        .collect()
+    }
+
+    // Applies the policy to every crossing, gives the new positions and velocities.
+    fn kept_inside(boundary: Boundary) -> Vec<(na::Point2<f32>, na::Vector2<f32>)> {
+        let mut rng = StdRng::seed_from_u64(0);
+        let inside = EntityParams::new(
+            na::Point2::new(50.0, 40.0),
+            RADIUS,
+            na::Vector2::new(1.0, 0.0),
+            10.0,
+        );
+        let mut still = inside;
+        assert!(!still.keep_inside(boundary, WORLD, &mut rng));
+        assert_eq!(still.get_center(), inside.get_center());
+        crossings()
+            .into_iter()
+            .map(|mut being| {
+                assert!(being.keep_inside(boundary, WORLD, &mut rng));
+                (being.get_center(), being.get_velocity())
+            })
+            .collect()
+    }
+
+    fn wrapped() -> Vec<(na::Point2<f32>, na::Vector2<f32>)> {
+        vec![
+            (na::Point2::new(105.0, 40.0), na::Vector2::new(-1.0, 0.0)),
+            (na::Point2::new(-5.0, 40.0), na::Vector2::new(1.0, 0.0)),
+            (na::Point2::new(50.0, 85.0), na::Vector2::new(0.0, -1.0)),
+            (na::Point2::new(50.0, -5.0), na::Vector2::new(0.0, 1.0)),
+        ]
+    }
+
+    #[test]
+    fn wrap_comes_back_on_the_opposite_side() {
+        assert_eq!(kept_inside(Boundary::Wrap), wrapped());
+    }
+
+    #[test]
+    fn recover_wraps() {
+        assert_eq!(kept_inside(Boundary::Recover), wrapped());
+    }
+
+    #[test]
+    fn clamp_stops_at_the_edge() {
+        assert_eq!(
+            kept_inside(Boundary::Clamp),
+            vec![
+                (na::Point2::new(5.0, 40.0), na::Vector2::new(-1.0, 0.0)),
+                (na::Point2::new(95.0, 40.0), na::Vector2::new(1.0, 0.0)),
+                (na::Point2::new(50.0, 5.0), na::Vector2::new(0.0, -1.0)),
+                (na::Point2::new(50.0, 75.0), na::Vector2::new(0.0, 1.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn bounce_turns_back_at_the_edge() {
+        assert_eq!(
+            kept_inside(Boundary::Bounce),
+            vec![
+                (na::Point2::new(5.0, 40.0), na::Vector2::new(1.0, 0.0)),
+                (na::Point2::new(95.0, 40.0), na::Vector2::new(-1.0, 0.0)),
+                (na::Point2::new(50.0, 5.0), na::Vector2::new(0.0, 1.0)),
+                (na::Point2::new(50.0, 75.0), na::Vector2::new(0.0, -1.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn respawn_comes_back_anywhere_inside() {
+        let crossings = crossings();
+        for (i, (center, velocity)) in kept_inside(Boundary::Respawn).into_iter().enumerate() {
+            assert!(center.x >= 0.0 && center.x < WORLD.0);
+            assert!(center.y >= 0.0 && center.y < WORLD.1);
+            assert_eq!(velocity, crossings[i].get_velocity());
+        }
+    }
+}
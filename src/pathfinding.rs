@@ -0,0 +1,312 @@
+use ggez::nalgebra as na;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+
+use crate::map::Map;
+
+// Costs of moving to a neighbouring cell, straight and diagonally.
+const STRAIGHT: u32 = 10;
+const DIAGONAL: u32 = 14;
+
+#[derive(PartialEq, Eq)]
+struct Node {
+    estimate: u32,
+    cell: usize,
+}
+
+// Reversed, so that `BinaryHeap` gives the node with the lowest estimate first.
+impl Ord for Node {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .estimate
+            .cmp(&self.estimate)
+            .then_with(|| other.cell.cmp(&self.cell))
+    }
+}
+
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Grid of cells a vehicle of the given radius can drive through without
+// hitting a building.
+pub struct NavGrid {
+    map: Map,
+    clearance: f32,
+    cell_size: f32,
+    cols: usize,
+    rows: usize,
+    blocked: Vec<bool>,
+}
+
+impl NavGrid {
+    pub fn new(map: &Map, width: f32, height: f32, cell_size: f32, clearance: f32) -> NavGrid {
+        let cell_size = cell_size.max(1.0);
+        let cols = ((width / cell_size).ceil() as usize).max(1);
+        let rows = ((height / cell_size).ceil() as usize).max(1);
+        let mut grid = NavGrid {
+            map: map.clone(),
+            clearance,
+            cell_size,
+            cols,
+            rows,
+            blocked: vec![false; cols * rows],
+        };
+        for cell in 0..cols * rows {
+            grid.blocked[cell] = map.is_blocked(grid.center(cell), clearance);
+        }
+        grid
+    }
+
+    fn cell(&self, p: na::Point2<f32>) -> usize {
+        let clamp = |v: f32, n: usize| -> usize {
+            if v <= 0.0 {
+                0
+            } else {
+                (v as usize).min(n - 1)
+            }
+        };
+        clamp(p.y / self.cell_size, self.rows) * self.cols + clamp(p.x / self.cell_size, self.cols)
+    }
+
+    fn center(&self, cell: usize) -> na::Point2<f32> {
+        na::Point2::new(
+            ((cell % self.cols) as f32 + 0.5) * self.cell_size,
+            ((cell / self.cols) as f32 + 0.5) * self.cell_size,
+        )
+    }
+
+    // Neighbouring free cells with the cost of getting there. Diagonal moves
+    // do not cut corners of blocked cells.
+    fn neighbours(&self, cell: usize) -> Vec<(usize, u32)> {
+        let (x, y) = ((cell % self.cols) as i64, (cell / self.cols) as i64);
+        let free = |x: i64, y: i64| -> bool {
+            x >= 0
+                && y >= 0
+                && (x as usize) < self.cols
+                && (y as usize) < self.rows
+                && !self.blocked[y as usize * self.cols + x as usize]
+        };
+        let mut found = Vec::with_capacity(8);
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                if (dx == 0 && dy == 0) || !free(x + dx, y + dy) {
+                    continue;
+                }
+                let cost = if dx != 0 && dy != 0 {
+                    if !free(x + dx, y) || !free(x, y + dy) {
+                        continue;
+                    }
+                    DIAGONAL
+                } else {
+                    STRAIGHT
+                };
+                found.push(((y + dy) as usize * self.cols + (x + dx) as usize, cost));
+            }
+        }
+        found
+    }
+
+    fn heuristic(&self, a: usize, b: usize) -> u32 {
+        let diff = |p: usize, q: usize| -> u32 { (p.max(q) - p.min(q)) as u32 };
+        let dx = diff(a % self.cols, b % self.cols);
+        let dy = diff(a / self.cols, b / self.cols);
+        STRAIGHT * dx.max(dy) + (DIAGONAL - STRAIGHT) * dx.min(dy)
+    }
+
+    // Closest free cell, searched in growing rings around the given one.
+    fn nearest_free(&self, cell: usize) -> Option<usize> {
+        if !self.blocked[cell] {
+            return Some(cell);
+        }
+        let mut visited = vec![false; self.blocked.len()];
+        let mut queue = VecDeque::new();
+        visited[cell] = true;
+        queue.push_back(cell);
+        while let Some(c) = queue.pop_front() {
+            let (x, y) = ((c % self.cols) as i64, (c / self.cols) as i64);
+            for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)].iter() {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx as usize >= self.cols || ny as usize >= self.rows {
+                    continue;
+                }
+                let n = ny as usize * self.cols + nx as usize;
+                if visited[n] {
+                    continue;
+                }
+                if !self.blocked[n] {
+                    return Some(n);
+                }
+                visited[n] = true;
+                queue.push_back(n);
+            }
+        }
+        None
+    }
+
+    // True if the vehicle can drive straight between the points without hitting a building.
+    fn line_of_sight(&self, a: na::Point2<f32>, b: na::Point2<f32>) -> bool {
+        let steps =
+            ((na::distance(&a, &b) / (self.clearance / 2.0).max(1.0)).ceil() as usize).max(1);
+        (0..=steps).all(|i| {
+            !self
+                .map
+                .is_blocked(a + (b - a) * (i as f32 / steps as f32), self.clearance)
+        })
+    }
+
+    // Waypoints of the shortest way from `from` to `to` found with A*, without
+    // `from` itself and ending at `to`. Straight parts are merged into single segments.
+    pub fn find_path(
+        &self,
+        from: na::Point2<f32>,
+        to: na::Point2<f32>,
+    ) -> Option<Vec<na::Point2<f32>>> {
+        let start = self.nearest_free(self.cell(from))?;
+        let goal = self.nearest_free(self.cell(to))?;
+
+        let mut cost = vec![u32::MAX; self.blocked.len()];
+        let mut came_from = vec![usize::MAX; self.blocked.len()];
+        let mut open = BinaryHeap::new();
+        cost[start] = 0;
+        open.push(Node {
+            estimate: self.heuristic(start, goal),
+            cell: start,
+        });
+        while let Some(Node { cell, estimate }) = open.pop() {
+            if cell == goal {
+                break;
+            }
+            if estimate > cost[cell] + self.heuristic(cell, goal) {
+                continue;
+            }
+            for (next, step) in self.neighbours(cell) {
+                let new_cost = cost[cell] + step;
+                if new_cost < cost[next] {
+                    cost[next] = new_cost;
+                    came_from[next] = cell;
+                    open.push(Node {
+                        estimate: new_cost + self.heuristic(next, goal),
+                        cell: next,
+                    });
+                }
+            }
+        }
+        if cost[goal] == u32::MAX {
+            return None;
+        }
+
+        let mut cells = vec![goal];
+        while let Some(&last) = cells.last() {
+            if last == start {
+                break;
+            }
+            cells.push(came_from[last]);
+        }
+        cells.reverse();
+
+        let mut points: Vec<na::Point2<f32>> = cells.iter().map(|&c| self.center(c)).collect();
+        points[0] = from;
+        points.push(to);
+        Some(self.smooth(points))
+    }
+
+    // Skips waypoints which can be seen from the previous kept one.
+    fn smooth(&self, points: Vec<na::Point2<f32>>) -> Vec<na::Point2<f32>> {
+        let mut path = Vec::new();
+        let mut current = 0;
+        while current < points.len() - 1 {
+            let mut next = points.len() - 1;
+            while next > current + 1 && !self.line_of_sight(points[current], points[next]) {
+                next -= 1;
+            }
+            path.push(points[next]);
+            current = next;
+        }
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Obstacle;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const CLEARANCE: f32 = 10.0;
+
+    fn crosses(map: &Map, from: na::Point2<f32>, path: &[na::Point2<f32>]) -> bool {
+        let mut a = from;
+        for &b in path {
+            if map
+                .get_obstacles()
+                .iter()
+                .any(|o| o.intersects_segment(a, b))
+            {
+                return true;
+            }
+            a = b;
+        }
+        false
+    }
+
+    #[test]
+    fn goes_around_a_wall() {
+        // Wall from the top, with a gap at the bottom.
+        let map = Map::new(vec![Obstacle::rect(190.0, 0.0, 20.0, 300.0)]);
+        let nav = NavGrid::new(&map, 400.0, 400.0, 20.0, CLEARANCE);
+        let from = na::Point2::new(50.0, 50.0);
+        let to = na::Point2::new(350.0, 50.0);
+
+        let path = nav.find_path(from, to).unwrap();
+        assert_eq!(path.last(), Some(&to));
+        assert!(path.iter().any(|p| p.y > 300.0));
+        assert!(!crosses(&map, from, &path));
+    }
+
+    #[test]
+    fn walled_in_target_is_unreachable() {
+        let map = Map::new(vec![
+            Obstacle::rect(200.0, 200.0, 100.0, 20.0),
+            Obstacle::rect(200.0, 280.0, 100.0, 20.0),
+            Obstacle::rect(200.0, 200.0, 20.0, 100.0),
+            Obstacle::rect(280.0, 200.0, 20.0, 100.0),
+        ]);
+        let nav = NavGrid::new(&map, 400.0, 400.0, 10.0, 1.0);
+        let inside = na::Point2::new(250.0, 250.0);
+        assert!(!map.is_blocked(inside, 1.0));
+        assert!(nav.find_path(na::Point2::new(50.0, 50.0), inside).is_none());
+        assert!(nav.find_path(inside, na::Point2::new(50.0, 50.0)).is_none());
+    }
+
+    #[test]
+    fn smoothed_paths_do_not_cross_buildings() {
+        let map = Map::new(vec![
+            Obstacle::rect(100.0, 80.0, 120.0, 60.0),
+            Obstacle::rect(300.0, 0.0, 40.0, 250.0),
+            Obstacle::rect(60.0, 260.0, 200.0, 40.0),
+            Obstacle::polygon(vec![
+                na::Point2::new(400.0, 300.0),
+                na::Point2::new(520.0, 320.0),
+                na::Point2::new(450.0, 400.0),
+            ]),
+        ]);
+        let nav = NavGrid::new(&map, 600.0, 450.0, 20.0, CLEARANCE);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut random_free = || loop {
+            let p = na::Point2::new(rng.gen_range(0.0, 600.0), rng.gen_range(0.0, 450.0));
+            if !map.is_blocked(p, CLEARANCE) {
+                return p;
+            }
+        };
+        for _ in 0..100 {
+            let (from, to) = (random_free(), random_free());
+            let path = nav.find_path(from, to).unwrap();
+            assert_eq!(path.last(), Some(&to));
+            assert!(!crosses(&map, from, &path), "{} -> {}", from, to);
+        }
+    }
+}
@@ -1,18 +1,41 @@
-use ggez::event::KeyCode;
 use ggez::nalgebra as na;
 use ggez::{graphics, Context, GameResult};
-use std::collections::HashSet;
+use rand::Rng;
 
+use crate::attack::{AttackKind, AttackParams, Cone, StaminaParams};
+use crate::gamesettings::GameSettings;
+use crate::moveable::Boundary;
 use crate::moveable::EntityParams;
 use crate::moveable::Moveable;
-use crate::moveable::Zone;
+use crate::simulation::{Input, DT};
+
+// Part of the stamina that has to come back before an exhausted player can attack again.
+const RECOVERY_FRACTION: f32 = 0.25;
 
 #[derive(Clone, Copy)]
 pub struct Player {
     ent_params: EntityParams,
-    sneeze_range: f32,
-    sneeze_max: f32,
-    is_sneezing: bool,
+    sneeze: AttackParams,
+    cough: AttackParams,
+    attack: Option<AttackKind>,
+    attack_range: f32,
+    // Reach of the current attack relative to its usual one, larger for charged attacks.
+    burst: f32,
+    stamina: f32,
+    stamina_params: StaminaParams,
+    // Out of stamina, no attacks until a part of it comes back.
+    exhausted: bool,
+    // Ticks left until the next attack can start.
+    cooldown: u32,
+    // Attack being charged and the number of ticks it has been charged for.
+    charging: Option<AttackKind>,
+    charge: u32,
+    // Unit vector towards the place the player aims at.
+    aim: na::Vector2<f32>,
+    // Chance of slipping away when caught.
+    resistance: f32,
+    // Ticks left until a caught player can move and attack again.
+    held: u32,
     citizens_infected: i32,
 }
 
@@ -29,75 +52,222 @@ impl Player {
         graphics::draw(ctx, &circle, graphics::DrawParam::default())
     }
 
-    pub fn draw_sneezing(self, ctx: &mut Context, color: graphics::Color) -> GameResult<()> {
-        let circle = graphics::Mesh::new_circle(
-            ctx,
-            graphics::DrawMode::fill(),
-            self.get_position(),
-            self.get_radius() + self.get_sneeze_range(),
-            2.0,
-            color,
-        )?;
-        graphics::draw(ctx, &circle, graphics::DrawParam::default())
+    pub fn draw_attack(self, ctx: &mut Context, color: graphics::Color) -> GameResult<()> {
+        let cone = match self.get_cone() {
+            Some(cone) => cone,
+            None => return Ok(()),
+        };
+        let mesh = if cone.is_full_circle() {
+            graphics::Mesh::new_circle(
+                ctx,
+                graphics::DrawMode::fill(),
+                cone.get_apex(),
+                cone.get_reach(),
+                2.0,
+                color,
+            )?
+        } else {
+            graphics::Mesh::new_polygon(ctx, graphics::DrawMode::fill(), &cone.get_points(), color)?
+        };
+        graphics::draw(ctx, &mesh, graphics::DrawParam::default())
     }
 
-    pub fn move_player(&mut self, width: f32, height: f32, pressed_keys: &HashSet<KeyCode>) {
-        let r = self.ent_params.get_radius();
-        match self.ent_params.where_is(width, height) {
-            Zone::LeftBorder => self.ent_params.set_cx(width + r),
-            Zone::RightBorder => self.ent_params.set_cx(-r),
-            Zone::BottomBorder => self.ent_params.set_cy(-r),
-            Zone::UpBorder => self.ent_params.set_cy(height + r),
-            Zone::Inside => {
-                let c = self.get_position();
-                let s = self.get_speed();
-
-                self.ent_params.set_velocity(na::Vector2::new(0.0, 0.0));
-                if pressed_keys.contains(&KeyCode::Left) {
-                    self.ent_params.set_cx(c.x - s);
-                };
-                if pressed_keys.contains(&KeyCode::Right) {
-                    self.ent_params.set_cx(c.x + s);
-                }
-                if pressed_keys.contains(&KeyCode::Up) {
-                    self.ent_params.set_cy(c.y - s);
-                }
-                if pressed_keys.contains(&KeyCode::Down) {
-                    self.ent_params.set_cy(c.y + s);
-                }
-                self.ent_params.move_step();
-            }
+    pub fn move_player<R: Rng + ?Sized>(
+        &mut self,
+        boundary: Boundary,
+        world: (f32, f32),
+        input: &Input,
+        dt: f32,
+        rng: &mut R,
+    ) {
+        let mut v = na::Vector2::new(0.0, 0.0);
+        if self.held > 0 {
+            self.held -= 1;
+            self.ent_params.set_velocity(v);
+            return;
+        }
+        if input.left {
+            v.x -= 1.0;
+        };
+        if input.right {
+            v.x += 1.0;
+        }
+        if input.up {
+            v.y -= 1.0;
         }
+        if input.down {
+            v.y += 1.0;
+        }
+        self.ent_params.set_velocity(v);
+        self.move_being(boundary, world, dt, rng);
     }
 
     pub fn infect(&mut self) {
         self.citizens_infected += 1
     }
 
-    pub fn get_sneeze_range(self) -> f32 {
-        self.sneeze_range
-    }
-
     pub fn get_infected(self) -> i32 {
         self.citizens_infected
     }
 
-    pub fn check_if_sneezing(self) -> bool {
-        self.is_sneezing
+    pub fn get_resistance(self) -> f32 {
+        self.resistance
     }
 
-    pub fn sneeze(&mut self) {
-        if self.is_sneezing {
-            if self.sneeze_range <= self.sneeze_max {
-                self.sneeze_range += 10.0;
-            } else {
-                self.sneeze_range = 0.0;
-            }
+    // Stops the player and any attack for the given number of ticks.
+    pub fn hold(&mut self, ticks: u32) {
+        self.held = ticks;
+        self.attack = None;
+        self.charging = None;
+        self.charge = 0;
+    }
+
+    pub fn is_held(self) -> bool {
+        self.held > 0
+    }
+
+    pub fn get_attack(self) -> Option<AttackKind> {
+        self.attack
+    }
+
+    pub fn is_attacking(self) -> bool {
+        self.attack.is_some()
+    }
+
+    fn get_attack_params(self, kind: AttackKind) -> AttackParams {
+        match kind {
+            AttackKind::Sneeze => self.sneeze,
+            AttackKind::Cough => self.cough,
         }
     }
 
-    pub fn set_sneeze(&mut self, s: bool) {
-        self.is_sneezing = s;
+    // Dose per second given by the current attack, at the player.
+    pub fn get_dose(self) -> f32 {
+        self.attack
+            .map_or(0.0, |kind| self.get_attack_params(kind).get_dose())
+    }
+
+    // Aerosol left by the current attack per second.
+    pub fn get_aerosol(self) -> f32 {
+        self.attack
+            .map_or(0.0, |kind| self.get_attack_params(kind).get_aerosol())
+    }
+
+    // Part of the town covered by the current attack.
+    pub fn get_cone(self) -> Option<Cone> {
+        self.attack.map(|kind| {
+            Cone::new(
+                self.get_position(),
+                self.aim,
+                self.get_radius() + self.attack_range,
+                self.get_attack_params(kind).get_angle(),
+            )
+        })
+    }
+
+    pub fn aim_at(&mut self, target: na::Point2<f32>) {
+        let v = target - self.get_position();
+        if v.norm() > 0.0 {
+            self.aim = v.normalize();
+        }
+    }
+
+    pub fn get_stamina(self) -> f32 {
+        self.stamina
+    }
+
+    pub fn get_max_stamina(self) -> f32 {
+        self.stamina_params.get_max()
+    }
+
+    pub fn is_exhausted(self) -> bool {
+        self.exhausted
+    }
+
+    pub fn get_cooldown(self) -> u32 {
+        self.cooldown
+    }
+
+    // Part of the full charge gathered so far, zero when nothing is charged.
+    pub fn get_charge(self) -> f32 {
+        self.charging.map_or(0.0, |kind| {
+            let charge_time = self.get_attack_params(kind).get_charge_time();
+            (self.charge as f32 / charge_time.max(1) as f32).min(1.0)
+        })
+    }
+
+    // A new attack starts from its smallest range.
+    fn start_attack(&mut self, kind: AttackKind, burst: f32) {
+        self.attack = Some(kind);
+        self.attack_range = self.get_attack_params(kind).get_range();
+        self.burst = burst;
+    }
+
+    fn use_stamina(&mut self, amount: f32) {
+        self.stamina -= amount;
+        if self.stamina <= 0.0 && amount > 0.0 {
+            self.stamina = 0.0;
+            self.exhausted = true;
+        }
+    }
+
+    // Advances attacks by one tick, `pressed` is the attack whose button is held.
+    pub fn update_attack(&mut self, pressed: Option<AttackKind>) {
+        self.cooldown = self.cooldown.saturating_sub(1);
+
+        // Attacks which are not charged last only as long as the button is held.
+        if let Some(kind) = self.attack {
+            if self.get_attack_params(kind).get_charge_time() == 0 && pressed != Some(kind) {
+                self.attack = None;
+            }
+        }
+
+        if self.attack.is_none() {
+            if let Some(kind) = self.charging {
+                let params = self.get_attack_params(kind);
+                if pressed == Some(kind) {
+                    self.charge = (self.charge + 1).min(params.get_charge_time());
+                } else {
+                    // The charge bursts out when the button is released.
+                    let burst = 1.0 + (params.get_charge_bonus() - 1.0) * self.get_charge();
+                    self.charging = None;
+                    self.charge = 0;
+                    self.start_attack(kind, burst);
+                }
+            } else if let Some(kind) = pressed {
+                if self.cooldown == 0 && !self.exhausted {
+                    if self.get_attack_params(kind).get_charge_time() > 0 {
+                        self.charging = Some(kind);
+                    } else {
+                        self.start_attack(kind, 1.0);
+                    }
+                }
+            }
+        }
+
+        match self.attack {
+            Some(kind) => {
+                let params = self.get_attack_params(kind);
+                self.attack_range += params.get_speed() * DT;
+                self.use_stamina(params.get_stamina_cost() * DT);
+                if self.attack_range >= params.get_max_range() * self.burst {
+                    self.attack = None;
+                    self.cooldown = params.get_cooldown();
+                } else if self.exhausted {
+                    self.attack = None;
+                }
+            }
+            // Stamina is held back while an attack is charged.
+            None if self.charging.is_some() => {}
+            None => {
+                let max = self.stamina_params.get_max();
+                self.stamina =
+                    (self.stamina + self.stamina_params.get_regeneration() * DT).min(max);
+                if self.exhausted && self.stamina >= max * RECOVERY_FRACTION {
+                    self.exhausted = false;
+                }
+            }
+        }
     }
 }
 
@@ -114,30 +284,159 @@ impl Moveable for Player {
         self.ent_params.get_speed()
     }
 
-    fn move_being(&mut self, width: f32, height: f32) {
-        let r = self.ent_params.get_radius();
-        match self.ent_params.where_is(width, height) {
-            Zone::LeftBorder => self.ent_params.set_cx(width + r),
-            Zone::RightBorder => self.ent_params.set_cx(-r),
-            Zone::BottomBorder => self.ent_params.set_cy(-r),
-            Zone::UpBorder => self.ent_params.set_cy(height + r),
-            Zone::Inside => self.ent_params.move_step(),
-        }
+    fn set_position(&mut self, p: na::Point2<f32>) {
+        self.ent_params.set_center(p);
+    }
+
+    fn move_being<R: Rng + ?Sized>(
+        &mut self,
+        boundary: Boundary,
+        world: (f32, f32),
+        dt: f32,
+        rng: &mut R,
+    ) {
+        self.ent_params.move_step(dt);
+        self.ent_params.keep_inside(boundary, world, rng);
     }
 }
 
-pub fn init(width: f32, height: f32) -> Player {
+pub fn init(settings: GameSettings) -> Player {
     let params: EntityParams = EntityParams::new(
-        na::Point2::new(width / 2.0, height / 2.0),
-        20.0,
+        na::Point2::new(
+            settings.get_world_width() / 2.0,
+            settings.get_world_height() / 2.0,
+        ),
+        settings.get_player_radius(),
         na::Vector2::new(0.0, 0.0),
-        5.0,
+        settings.get_player_speed(),
     );
     Player {
         ent_params: params,
-        sneeze_range: 5.0,
-        sneeze_max: 30.0,
-        is_sneezing: false,
+        sneeze: settings.get_sneeze(),
+        cough: settings.get_cough(),
+        attack: None,
+        attack_range: 0.0,
+        burst: 1.0,
+        stamina: settings.get_stamina().get_max(),
+        stamina_params: settings.get_stamina(),
+        exhausted: false,
+        cooldown: 0,
+        charging: None,
+        charge: 0,
+        aim: na::Vector2::new(0.0, -1.0),
+        resistance: settings.get_player_resistance(),
+        held: 0,
         citizens_infected: 0,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player(settings: &str) -> Player {
+        init(GameSettings::from_toml(settings).unwrap())
+    }
+
+    // Holds the sneeze for `ticks` ticks, then lets it go until the attack is
+    // over. Gives the charge gathered and the largest range reached.
+    fn burst(player: &mut Player, ticks: u32) -> (f32, f32) {
+        for _ in 0..ticks {
+            player.update_attack(Some(AttackKind::Sneeze));
+        }
+        let charge = player.get_charge();
+        let mut range: f32 = 0.0;
+        player.update_attack(None);
+        while let Some(cone) = player.get_cone() {
+            range = range.max(cone.get_reach() - player.get_radius());
+            player.update_attack(None);
+        }
+        (charge, range)
+    }
+
+    #[test]
+    fn exhausted_player_rests_until_a_part_of_stamina_comes_back() {
+        let mut player = player(
+            "[sneeze]\n\
+             speed = 60.0\n\
+             stamina_cost = 600.0\n\
+             cooldown = 0\n\
+             [stamina]\n\
+             max = 10.0\n\
+             regeneration = 60.0\n",
+        );
+        player.update_attack(Some(AttackKind::Sneeze));
+        assert!(player.is_exhausted());
+        assert!(!player.is_attacking());
+        assert_eq!(player.get_stamina(), 0.0);
+
+        let recovered = player.get_max_stamina() * RECOVERY_FRACTION;
+        let mut ticks = 0;
+        while player.is_exhausted() {
+            assert!(player.get_stamina() < recovered);
+            player.update_attack(Some(AttackKind::Sneeze));
+            assert!(!player.is_attacking());
+            ticks += 1;
+        }
+        assert!(player.get_stamina() >= recovered);
+        assert_eq!(ticks, 3);
+
+        // The next sneeze goes out and uses up the stamina again.
+        player.update_attack(Some(AttackKind::Sneeze));
+        assert!(player.is_exhausted());
+        assert_eq!(player.get_stamina(), 0.0);
+    }
+
+    #[test]
+    fn cooldown_blocks_new_attacks() {
+        let mut player = player("[sneeze]\ncooldown = 20\n");
+        player.update_attack(Some(AttackKind::Sneeze));
+        while player.is_attacking() {
+            player.update_attack(Some(AttackKind::Sneeze));
+        }
+        assert_eq!(player.get_cooldown(), 20);
+        for _ in 0..19 {
+            player.update_attack(Some(AttackKind::Sneeze));
+            assert!(!player.is_attacking());
+        }
+        player.update_attack(Some(AttackKind::Sneeze));
+        assert!(player.is_attacking());
+    }
+
+    #[test]
+    fn charged_burst_reaches_further() {
+        let settings = "[sneeze]\n\
+                        range = 0.0\n\
+                        max_range = 30.0\n\
+                        speed = 60.0\n\
+                        cooldown = 0\n\
+                        charge_time = 30\n\
+                        charge_bonus = 2.0\n";
+        for &(ticks, charge) in &[(1, 0.0), (16, 0.5), (31, 1.0), (90, 1.0)] {
+            let mut player = player(settings);
+            let (gathered, range) = burst(&mut player, ticks);
+            assert!((gathered - charge).abs() < 1e-6, "{} ticks", ticks);
+            let reach = 30.0 * (1.0 + charge);
+            assert!(range < reach && range >= reach - 1.5, "{} ticks", ticks);
+        }
+    }
+
+    #[test]
+    fn stamina_is_held_back_while_charging() {
+        let mut player = player(
+            "[sneeze]\n\
+             speed = 60.0\n\
+             cooldown = 0\n\
+             charge_time = 30\n",
+        );
+        burst(&mut player, 1);
+        let stamina = player.get_stamina();
+        assert!(stamina < player.get_max_stamina());
+        for _ in 0..10 {
+            player.update_attack(Some(AttackKind::Sneeze));
+        }
+        assert_eq!(player.get_stamina(), stamina);
+        player.update_attack(None);
+        assert!(player.is_attacking());
+    }
+}
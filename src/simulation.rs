@@ -0,0 +1,1384 @@
+use ggez::nalgebra as na;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::aerosol::AerosolField;
+use crate::ambulance::{self, Ambulance};
+use crate::attack::{AttackKind, Cone};
+use crate::authority::Authority;
+use crate::cellar::{self, Cellar};
+use crate::citizen::{self, Citizen};
+use crate::dispatcher::Dispatcher;
+use crate::gamesettings::GameSettings;
+use crate::hospital::{self, Hospital};
+use crate::infectable::{DiseaseState, Infectable, InfectionSource};
+use crate::map::Map;
+use crate::moveable::Moveable;
+use crate::pathfinding::NavGrid;
+use crate::player::{self, Player};
+use crate::spatial::SpatialGrid;
+use crate::strain::{self, Strain};
+
+// The simulation always advances by the same time step, independently of the frame rate.
+pub const TICKS_PER_SECOND: u32 = 60;
+pub const DT: f32 = 1.0 / TICKS_PER_SECOND as f32;
+
+// Number of steps between two random changes of citizen direction.
+const ANGLE_CHANGE_PERIOD: u64 = TICKS_PER_SECOND as u64;
+
+// Size of cells of the grid ambulances plan their routes on.
+const NAV_CELL_SIZE: f32 = 20.0;
+
+// Number of tries to find a free place for a cellar.
+const MAX_PLACEMENT_ATTEMPTS: u32 = 100;
+
+// State of the controls for a single simulation step.
+#[derive(Copy, Clone, Default)]
+pub struct Input {
+    pub left: bool,
+    pub right: bool,
+    pub up: bool,
+    pub down: bool,
+    pub sneeze: bool,
+    pub cough: bool,
+    // Point of the town the player aims at, the last direction is kept if not given.
+    pub aim: Option<na::Point2<f32>>,
+}
+
+// Summary of the current state of the simulation.
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub struct Stats {
+    pub ticks: u64,
+    pub citizens: usize,
+    pub susceptible: usize,
+    pub exposed: usize,
+    pub infectious: usize,
+    pub recovered: usize,
+    pub dead: usize,
+    // Exposed and infectious citizens without symptoms.
+    pub asymptomatic: usize,
+    // Infectious citizens with symptoms, the cases the health authority knows about.
+    pub symptomatic: usize,
+    // Number of strains that appeared so far, the one of the player included.
+    pub strains: usize,
+    pub hidden: usize,
+    pub infected: i32,
+    pub infected_by_player: i32,
+    pub infected_by_citizens: i32,
+    // Patients waiting for an ambulance and wait times of the picked up ones, in ticks.
+    pub waiting: usize,
+    pub picked_up: u32,
+    pub average_wait: f32,
+    pub max_wait: u64,
+    pub beds: u32,
+    pub occupied_beds: u32,
+    pub admitted: u32,
+    // All beds are taken while patients are waiting for an ambulance.
+    pub overflow: bool,
+    // Countermeasures of the health authority so far and the money it has left.
+    pub countermeasures: u32,
+    pub budget: f32,
+    pub vaccinated: u32,
+    // Citizens found by testing stations who are still isolated.
+    pub isolated: usize,
+    pub player_caught: u32,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Ticks: {}", self.ticks)?;
+        writeln!(f, "Citizens: {}", self.citizens)?;
+        writeln!(f, "Susceptible: {}", self.susceptible)?;
+        writeln!(f, "Exposed: {}", self.exposed)?;
+        writeln!(f, "Infectious: {}", self.infectious)?;
+        writeln!(f, "Recovered: {}", self.recovered)?;
+        writeln!(f, "Dead: {}", self.dead)?;
+        writeln!(f, "Asymptomatic: {}", self.asymptomatic)?;
+        writeln!(f, "Symptomatic: {}", self.symptomatic)?;
+        writeln!(f, "Strains: {}", self.strains)?;
+        writeln!(f, "Hidden in cellars: {}", self.hidden)?;
+        writeln!(f, "Infected: {}", self.infected)?;
+        writeln!(f, "Infected by player: {}", self.infected_by_player)?;
+        writeln!(f, "Infected by citizens: {}", self.infected_by_citizens)?;
+        writeln!(f, "Waiting for ambulance: {}", self.waiting)?;
+        writeln!(f, "Picked up: {}", self.picked_up)?;
+        writeln!(
+            f,
+            "Average wait: {:.2} s",
+            self.average_wait / TICKS_PER_SECOND as f32
+        )?;
+        writeln!(
+            f,
+            "Max wait: {:.2} s",
+            self.max_wait as f32 / TICKS_PER_SECOND as f32
+        )?;
+        writeln!(f, "Beds: {}/{}", self.occupied_beds, self.beds)?;
+        writeln!(f, "Admitted to hospital: {}", self.admitted)?;
+        writeln!(f, "Countermeasures: {}", self.countermeasures)?;
+        writeln!(f, "Authority budget: {:.0}", self.budget)?;
+        writeln!(f, "Vaccinated: {}", self.vaccinated)?;
+        writeln!(f, "Isolated: {}", self.isolated)?;
+        write!(f, "Player caught: {}", self.player_caught)
+    }
+}
+
+pub struct Simulation {
+    settings: GameSettings,
+    // Size of the town, taken from the map or from the settings.
+    world: (f32, f32),
+    player: Player,
+    citizens: Vec<Citizen>,
+    ambulances: Vec<Ambulance>,
+    dispatcher: Dispatcher,
+    hospitals: Vec<Hospital>,
+    cellars: Vec<Cellar>,
+    map: Map,
+    nav: NavGrid,
+    // Rest of the route of each ambulance, after its current destination.
+    routes: Vec<VecDeque<na::Point2<f32>>>,
+    // Positions from before the last step, used to interpolate rendering.
+    prev_player: na::Point2<f32>,
+    prev_citizens: Vec<na::Point2<f32>>,
+    prev_ambulances: Vec<na::Point2<f32>>,
+    grid: SpatialGrid,
+    aerosol: AerosolField,
+    // All strains that appeared so far, the first one is the strain of the player.
+    strains: Vec<Strain>,
+    authority: Authority,
+    vaccinated: u32,
+    player_caught: u32,
+    max_citizen_radius: f32,
+    secondary_cases: i32,
+    ticks: u64,
+    seed: u64,
+    rng: StdRng,
+}
+
+impl Simulation {
+    // The same seed and the same sequence of inputs always give the same run.
+    pub fn new(settings: GameSettings, seed: u64) -> Simulation {
+        Simulation::with_map(settings, Map::default(), seed)
+    }
+
+    pub fn with_map(settings: GameSettings, map: Map, seed: u64) -> Simulation {
+        let mut rng = StdRng::seed_from_u64(seed);
+        // Maps without a size of their own take the town size from the settings.
+        let world = map
+            .get_size()
+            .unwrap_or((settings.get_world_width(), settings.get_world_height()));
+
+        // List of random citizens, in their spawn areas if the map has any, pushed out of buildings.
+        let mut l = Vec::new();
+        for i in 0..(settings.get_citizens_quan()) {
+            let mut cit = citizen::random_citizen(settings, i as usize, world, &mut rng);
+            let spawns = map.get_citizen_spawns();
+            if !spawns.is_empty() {
+                let area = spawns[rng.gen_range(0, spawns.len())];
+                cit.set_position(na::Point2::new(
+                    area.x + rng.gen::<f32>() * area.w,
+                    area.y + rng.gen::<f32>() * area.h,
+                ));
+            }
+            cit.set_position(map.collide(cit.get_position(), cit.get_radius()).0);
+            l.push(cit);
+        }
+        let hospitals: Vec<Hospital> = if map.get_hospitals().is_empty() {
+            vec![hospital::new(settings.get_hospital())]
+        } else {
+            map.get_hospitals()
+                .iter()
+                .map(|h| hospital::at(settings.get_hospital(), h.get_position(), h.get_size()))
+                .collect()
+        };
+        let cellar_params = settings.get_cellars();
+        let mut cellars = Vec::new();
+        for site in map.get_cellars() {
+            cellars.push(cellar::new(
+                cellar_params,
+                site.get_position(),
+                site.get_size(),
+            ));
+        }
+        if cellars.is_empty() {
+            for _ in 0..cellar_params.get_quantity() {
+                // Cellars are not placed inside of buildings, unless there is no room left.
+                let mut c = cellar::random(cellar_params, world, &mut rng);
+                for _ in 0..MAX_PLACEMENT_ATTEMPTS {
+                    if !map.is_blocked(c.get_position(), c.get_size() / 2.0) {
+                        break;
+                    }
+                    c = cellar::random(cellar_params, world, &mut rng);
+                }
+                cellars.push(c);
+            }
+        }
+        // Ambulances are shared between depots, or wait at the first hospital.
+        let mut ambulances = Vec::new();
+        for i in 0..(settings.get_ambulance_quan()) {
+            let depots = map.get_depots();
+            let start = if depots.is_empty() {
+                hospitals[0].get_position()
+            } else {
+                depots[i as usize % depots.len()]
+            };
+            ambulances.push(ambulance::new(settings, start));
+        }
+
+        // Cells are big enough to find all contacts of a citizen in the neighbouring cells.
+        let max_citizen_radius = l.iter().map(|c| c.get_radius()).fold(0.0, f32::max);
+        let grid = SpatialGrid::new(
+            world.0,
+            world.1,
+            2.0 * max_citizen_radius + settings.get_disease().get_transmission_radius(),
+        );
+
+        let aerosol = AerosolField::new(settings.get_aerosol(), &map, world.0, world.1);
+        let nav = NavGrid::new(&map, world.0, world.1, NAV_CELL_SIZE, ambulance::RADIUS);
+
+        let strain = settings.get_strain();
+        let authority = Authority::new(settings.get_authority());
+        let mut player = player::init(settings);
+        let spawn = map
+            .get_player_spawn()
+            .unwrap_or_else(|| na::Point2::new(world.0 / 2.0, world.1 / 2.0));
+        player.set_position(map.collide(spawn, player.get_radius()).0);
+        Simulation {
+            settings,
+            world,
+            prev_player: player.get_position(),
+            prev_citizens: l.iter().map(|c| c.get_position()).collect(),
+            prev_ambulances: ambulances.iter().map(|a| a.get_position()).collect(),
+            routes: vec![VecDeque::new(); ambulances.len()],
+            player,
+            citizens: l,
+            ambulances,
+            dispatcher: Dispatcher::new(),
+            hospitals,
+            cellars,
+            map,
+            nav,
+            grid,
+            aerosol,
+            strains: vec![strain],
+            authority,
+            vaccinated: 0,
+            player_caught: 0,
+            max_citizen_radius,
+            secondary_cases: 0,
+            ticks: 0,
+            seed,
+            rng,
+        }
+    }
+
+    pub fn get_settings(&self) -> GameSettings {
+        self.settings
+    }
+
+    pub fn get_world_size(&self) -> (f32, f32) {
+        self.world
+    }
+
+    pub fn get_player(&self) -> &Player {
+        &self.player
+    }
+
+    pub fn get_citizens(&self) -> &[Citizen] {
+        &self.citizens
+    }
+
+    pub fn get_ambulances(&self) -> &[Ambulance] {
+        &self.ambulances
+    }
+
+    pub fn get_hospitals(&self) -> &[Hospital] {
+        &self.hospitals
+    }
+
+    pub fn get_cellars(&self) -> &[Cellar] {
+        &self.cellars
+    }
+
+    pub fn get_map(&self) -> &Map {
+        &self.map
+    }
+
+    pub fn get_aerosol(&self) -> &AerosolField {
+        &self.aerosol
+    }
+
+    pub fn get_strains(&self) -> &[Strain] {
+        &self.strains
+    }
+
+    pub fn get_authority(&self) -> &Authority {
+        &self.authority
+    }
+
+    // Points the ambulance is still going to drive through, for debug drawing.
+    pub fn get_route(&self, id: usize) -> Vec<na::Point2<f32>> {
+        let amb = self.ambulances[id];
+        if amb.is_free() {
+            return Vec::new();
+        }
+        let mut route = vec![amb.get_position(), amb.destination];
+        route.extend(self.routes[id].iter());
+        route
+    }
+
+    // Copies of the entities placed `alpha` of the way between their
+    // positions before and after the last step.
+    pub fn get_interpolated_player(&self, alpha: f32) -> Player {
+        let mut player = self.player;
+        player.set_position(self.interpolate(self.prev_player, player.get_position(), alpha));
+        player
+    }
+
+    pub fn get_interpolated_citizen(&self, id: usize, alpha: f32) -> Citizen {
+        let mut cit = self.citizens[id];
+        cit.set_position(self.interpolate(self.prev_citizens[id], cit.get_position(), alpha));
+        cit
+    }
+
+    pub fn get_interpolated_ambulance(&self, id: usize, alpha: f32) -> Ambulance {
+        let mut ambulance = self.ambulances[id];
+        ambulance.set_position(self.interpolate(
+            self.prev_ambulances[id],
+            ambulance.get_position(),
+            alpha,
+        ));
+        ambulance
+    }
+
+    fn interpolate(
+        &self,
+        prev: na::Point2<f32>,
+        curr: na::Point2<f32>,
+        alpha: f32,
+    ) -> na::Point2<f32> {
+        // Jumps, like wrapping around the town, are not smoothed.
+        let max_jump = self.world.0.min(self.world.1) / 2.0;
+        if na::distance(&prev, &curr) > max_jump {
+            curr
+        } else {
+            prev + (curr - prev) * alpha
+        }
+    }
+
+    pub fn get_ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn get_stats(&self) -> Stats {
+        let count = |state: DiseaseState| {
+            self.citizens
+                .iter()
+                .filter(|c| c.get_disease_state() == state)
+                .count()
+        };
+        let beds = self.hospitals.iter().map(|h| h.get_beds()).sum();
+        Stats {
+            ticks: self.ticks,
+            citizens: self.citizens.len(),
+            susceptible: count(DiseaseState::Susceptible),
+            exposed: count(DiseaseState::Exposed),
+            infectious: count(DiseaseState::Infectious),
+            recovered: count(DiseaseState::Recovered),
+            dead: count(DiseaseState::Dead),
+            asymptomatic: self.citizens.iter().filter(|c| c.is_asymptomatic()).count(),
+            symptomatic: self.citizens.iter().filter(|c| is_known_case(**c)).count(),
+            strains: self.strains.len(),
+            hidden: self.cellars.iter().map(|c| c.get_hidden() as usize).sum(),
+            infected: self.get_infected(),
+            infected_by_player: self.player.get_infected(),
+            infected_by_citizens: self.secondary_cases,
+            waiting: self.dispatcher.get_queue_len(),
+            picked_up: self.dispatcher.get_served(),
+            average_wait: self.dispatcher.get_average_wait(),
+            max_wait: self.dispatcher.get_max_wait(),
+            beds,
+            occupied_beds: self.hospitals.iter().map(|h| h.get_occupied()).sum(),
+            admitted: self.hospitals.iter().map(|h| h.get_admitted()).sum(),
+            overflow: self.dispatcher.get_queue_len() > 0
+                && self.hospitals.iter().all(|h| !h.has_free_bed()),
+            countermeasures: self.authority.get_deployed(),
+            budget: self.authority.get_budget(),
+            vaccinated: self.vaccinated,
+            isolated: self.citizens.iter().filter(|c| c.is_isolated()).count(),
+            player_caught: self.player_caught,
+        }
+    }
+
+    // All citizens infected so far, by the player and by other citizens.
+    pub fn get_infected(&self) -> i32 {
+        self.player.get_infected() + self.secondary_cases
+    }
+
+    // Ids of citizens whose centers are not further than `r` from `point`,
+    // as of the beginning of the current step.
+    pub fn query_radius(&self, point: na::Point2<f32>, r: f32) -> Vec<usize> {
+        self.grid.query_radius(point, r)
+    }
+
+    pub fn get_max_citizen_radius(&self) -> f32 {
+        self.max_citizen_radius
+    }
+
+    // Buildings stop the sneeze.
+    fn is_victim(cit: Citizen, cone: Cone, map: &Map) -> bool {
+        cone.hits(cit.get_position(), cit.get_radius())
+            && map.is_visible(cone.get_apex(), cit.get_position())
+    }
+
+    // Input of a player who walks to the nearest healthy citizen in the streets and sneezes
+    // at it once it is in reach, for runs without anybody at the controls.
+    pub fn autopilot(&self) -> Input {
+        let position = self.player.get_position();
+        let target = nearest(
+            self.citizens
+                .iter()
+                .filter(|c| c.get_disease_state() == DiseaseState::Susceptible && !c.is_hidden())
+                .map(|c| (c.get_id(), c.get_position())),
+            position,
+        );
+        let target = match target {
+            Some(id) => self.citizens[id],
+            None => return Input::default(),
+        };
+        let offset = target.get_position() - position;
+        let reach = self.player.get_radius()
+            + self.settings.get_sneeze().get_max_range()
+            + target.get_radius();
+        // Stops a bit before the citizen, so the sneeze has room to spread.
+        let close = offset.norm() < reach * 0.75;
+        let slack = self.player.get_radius() / 2.0;
+        Input {
+            left: !close && offset.x < -slack,
+            right: !close && offset.x > slack,
+            up: !close && offset.y < -slack,
+            down: !close && offset.y > slack,
+            sneeze: offset.norm() < reach,
+            cough: false,
+            aim: Some(target.get_position()),
+        }
+    }
+
+    // Citizens take a dose of the virus from the attack of the player, smaller
+    // further from the player, and from the clouds they walk through.
+    fn infection(&mut self) {
+        let dose_params = self.settings.get_dose();
+        let strain = self.strains[0];
+        let transmission = self.authority.get_transmission();
+        let mut exposure = vec![0.0; self.citizens.len()];
+        if let Some(cone) = self.player.get_cone() {
+            let reach = cone.get_reach() + self.max_citizen_radius;
+            let dose = self.player.get_dose();
+            for id in self.grid.query_radius(cone.get_apex(), reach) {
+                let cit = self.citizens[id];
+                if Simulation::is_victim(cit, cone, &self.map) {
+                    let distance =
+                        na::distance(&cone.get_apex(), &cit.get_position()) - cit.get_radius();
+                    exposure[id] = dose * dose_params.falloff(distance);
+                }
+            }
+        }
+
+        for (cit, from_attack) in self.citizens.iter_mut().zip(exposure) {
+            // Patients in hospital are looked after by the hospital.
+            if cit.is_in_hospital() {
+                continue;
+            }
+            cit.progress_disease(self.settings.get_disease(), &mut self.rng);
+            let state = cit.get_disease_state();
+            if cit.is_isolated()
+                && state != DiseaseState::Exposed
+                && state != DiseaseState::Infectious
+            {
+                cit.release();
+            }
+            let from_cloud = if cit.is_hidden() {
+                0.0
+            } else {
+                self.aerosol.concentration_at(cit.get_position())
+            };
+            let dose =
+                (from_attack + from_cloud) * strain.get_transmissibility() * transmission * DT;
+            if dose > 0.0 {
+                if cit.absorb_dose(dose, strain, dose_params.get_response(), &mut self.rng) {
+                    self.player.infect();
+                }
+            } else {
+                cit.clear_dose(dose_params.get_clearance());
+            }
+            if cit.needs_doctor() && !cit.is_in_cellar() {
+                self.dispatcher.call(cit.get_id(), self.ticks);
+            } else {
+                self.dispatcher.cancel(cit.get_id());
+            }
+        }
+    }
+
+    // Sends the nearest free ambulance to each patient in the queue, as long as
+    // there are free ambulances and free beds in hospitals.
+    fn dispatch(&mut self) {
+        while let Some(id) = self.dispatcher.next_patient() {
+            let patient = self.citizens[id].get_position();
+            let hospital = nearest(
+                self.hospitals
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, h)| h.has_free_bed())
+                    .map(|(i, h)| (i, h.get_position())),
+                patient,
+            );
+            let ambulance = nearest(
+                self.ambulances
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, a)| a.is_free())
+                    .map(|(i, a)| (i, a.get_position())),
+                patient,
+            );
+            let (h, a) = match (hospital, ambulance) {
+                (Some(h), Some(a)) => (h, a),
+                _ => break,
+            };
+            self.dispatcher.assign(id);
+            self.hospitals[h].reserve_bed();
+            self.citizens[id].stop();
+            let first = self.plan_route(a, patient);
+            self.ambulances[a].set_destination(id, first, h);
+        }
+    }
+
+    // Finds the way around buildings, keeps it for the ambulance and returns its first point.
+    fn plan_route(&mut self, a: usize, to: na::Point2<f32>) -> na::Point2<f32> {
+        let path = self
+            .nav
+            .find_path(self.ambulances[a].get_position(), to)
+            .unwrap_or_else(|| vec![to]);
+        let mut route: VecDeque<na::Point2<f32>> = path.into_iter().collect();
+        let first = route.pop_front().unwrap_or(to);
+        self.routes[a] = route;
+        first
+    }
+
+    fn drive_ambulances(&mut self) {
+        for a in 0..self.ambulances.len() {
+            let amb = &mut self.ambulances[a];
+            let route = &mut self.routes[a];
+            if amb.has_arrived() {
+                if let Some(next) = route.pop_front() {
+                    amb.head_to(next);
+                }
+            }
+            let id = match amb.get_patient_id() {
+                Some(id) => id,
+                None => continue,
+            };
+            let at_end = amb.has_arrived() && route.is_empty();
+            let hospital = &mut self.hospitals[amb.get_hospital_id()];
+            let patient = &mut self.citizens[id];
+            if amb.carries_patient() {
+                patient.go_hospital(amb.get_position());
+                if at_end {
+                    // Patients who got better on the way are left at the door.
+                    if patient.needs_doctor() {
+                        hospital.admit(id);
+                        patient.admit_to_hospital();
+                    } else {
+                        hospital.cancel_reservation();
+                    }
+                    self.dispatcher.cancel(id);
+                    amb.finish_act();
+                }
+            } else if !patient.needs_doctor() {
+                // The patient got better or died before the ambulance came.
+                hospital.cancel_reservation();
+                amb.finish_act();
+                route.clear();
+            } else if self
+                .grid
+                .query_radius(
+                    amb.get_position(),
+                    amb.get_radius() + self.max_citizen_radius,
+                )
+                .contains(&id)
+            {
+                let hospital_position = hospital.get_position();
+                patient.go_hospital(amb.get_position());
+                self.dispatcher.pick_up(id, self.ticks);
+                let first = self.plan_route(a, hospital_position);
+                self.ambulances[a].pick_up(first);
+            }
+        }
+    }
+
+    fn hiding(&mut self) {
+        for cellar in self.cellars.iter_mut() {
+            for id in cellar.update() {
+                self.citizens[id].leave_cellar();
+            }
+        }
+
+        // Healthy citizens who hear the sneeze or cough run to the nearest cellar with room left.
+        if self.player.is_attacking() {
+            let alarm_radius = self.settings.get_cellars().get_alarm_radius();
+            for id in self
+                .grid
+                .query_radius(self.player.get_position(), alarm_radius)
+            {
+                let cit = &mut self.citizens[id];
+                if cit.get_disease_state() != DiseaseState::Susceptible
+                    || cit.get_shelter().is_some()
+                    || cit.get_speed() == 0.0
+                {
+                    continue;
+                }
+                let shelter = nearest(
+                    self.cellars
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, c)| c.has_room())
+                        .map(|(i, c)| (i, c.get_position())),
+                    cit.get_position(),
+                );
+                if let Some(c) = shelter {
+                    cit.flee_to(c, self.cellars[c].get_position());
+                }
+            }
+        }
+
+        // The cellar may get full before the citizen reaches it.
+        for cit in self.citizens.iter_mut() {
+            let cellar = match cit.get_shelter() {
+                Some(c) => &mut self.cellars[c],
+                None => continue,
+            };
+            if cit.get_disease_state() != DiseaseState::Susceptible {
+                cit.stop_fleeing();
+            } else if cellar.is_at_door(cit.get_position()) {
+                if cellar.has_room() {
+                    cellar.hide(cit.get_id());
+                    cit.hide_in_cellar();
+                } else {
+                    cit.stop_fleeing();
+                }
+            } else {
+                cit.aim_at(cellar.get_position());
+            }
+        }
+    }
+
+    // Moves everybody out of buildings they walked into, so that they slide along the walls.
+    // Closed areas come first, so nothing is pushed out of them into a building.
+    fn collisions(&mut self) {
+        let radius = self.player.get_radius();
+        let (center, _) = close_off(
+            &self.authority,
+            self.prev_player,
+            self.player.get_position(),
+            radius,
+        );
+        let (center, _) = self.map.collide(center, radius);
+        self.player.set_position(center);
+
+        for (cit, &prev) in self.citizens.iter_mut().zip(self.prev_citizens.iter()) {
+            if cit.is_hidden() {
+                continue;
+            }
+            let (center, closed) =
+                close_off(&self.authority, prev, cit.get_position(), cit.get_radius());
+            let (center, hit) = self.map.collide(center, cit.get_radius());
+            cit.set_position(center);
+            if let Some(normal) = hit.or(closed) {
+                cit.slide_along(normal);
+            }
+        }
+
+        for amb in self.ambulances.iter_mut() {
+            let (center, _) = self.map.collide(amb.get_position(), amb.get_radius());
+            amb.set_position(center);
+        }
+    }
+
+    fn treatment(&mut self) {
+        for hospital in self.hospitals.iter_mut() {
+            for id in hospital.treat() {
+                self.citizens[id].discharge(hospital.get_position());
+            }
+        }
+    }
+
+    // Infectious citizens pass their strain to the ones around them, which
+    // may mutate on the way.
+    fn transmission(&mut self) {
+        let disease = self.settings.get_disease();
+        let mutation = self.settings.get_mutation();
+        let transmission = self.authority.get_transmission();
+        let sources: Vec<(usize, na::Point2<f32>, f32, Strain)> = self
+            .citizens
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| {
+                c.get_disease_state() == DiseaseState::Infectious
+                    && !c.is_hidden()
+                    && !c.is_isolated()
+            })
+            .map(|(i, c)| {
+                (
+                    i,
+                    c.get_position(),
+                    c.get_radius(),
+                    c.get_strain().unwrap_or(self.strains[0]),
+                )
+            })
+            .collect();
+
+        for (src, position, radius, strain) in sources {
+            let mut cases = 0;
+            let reach = radius + self.max_citizen_radius + disease.get_transmission_radius();
+            for id in self.grid.query_radius(position, reach) {
+                let cit = &mut self.citizens[id];
+                let state = cit.get_disease_state();
+                if state != DiseaseState::Susceptible && state != DiseaseState::Recovered {
+                    continue;
+                }
+                let dist = na::distance(&position, &cit.get_position());
+                if dist >= radius + cit.get_radius() + disease.get_transmission_radius()
+                    || self.rng.gen::<f32>()
+                        >= disease.get_transmission_probability()
+                            * strain.get_transmissibility()
+                            * transmission
+                            * cit.get_susceptibility()
+                {
+                    continue;
+                }
+                let mutated = self.strains.len() < strain::MAX_STRAINS
+                    && self.rng.gen::<f32>() < mutation.get_probability();
+                let passed = if mutated {
+                    strain.mutate(self.strains.len(), mutation.get_variation(), &mut self.rng)
+                } else {
+                    strain
+                };
+                if cit.become_exposed(InfectionSource::Citizen(src), passed, &mut self.rng) {
+                    if mutated {
+                        self.strains.push(passed);
+                    }
+                    cases += 1;
+                }
+            }
+            self.citizens[src].add_cases(cases);
+            self.secondary_cases += cases as i32;
+        }
+    }
+
+    // The health authority reviews the stats from time to time and pays for
+    // countermeasures, which are then carried out here.
+    fn countermeasures(&mut self) {
+        self.authority.update();
+        let params = self.authority.get_params();
+        if self.ticks > 0
+            && self
+                .ticks
+                .is_multiple_of(u64::from(params.get_review_period()))
+        {
+            let stats = self.get_stats();
+            let citizens = &self.citizens;
+            let grid = &self.grid;
+            let radius = params.get_closure().get_size() / 2.0;
+            self.authority
+                .review(&stats, || hotspot(citizens, grid, radius));
+        }
+        self.testing();
+        self.vaccination();
+    }
+
+    // Testing stations isolate infected citizens they find and hold the player.
+    fn testing(&mut self) {
+        let testing = self.authority.get_params().get_testing();
+        let radius = testing.get_radius();
+        let chance = testing.get_detection() * DT;
+        let stations: Vec<na::Point2<f32>> = self
+            .authority
+            .get_stations()
+            .iter()
+            .map(|s| s.get_position())
+            .collect();
+        for position in stations {
+            for id in self
+                .grid
+                .query_radius(position, radius + self.max_citizen_radius)
+            {
+                let cit = &mut self.citizens[id];
+                let state = cit.get_disease_state();
+                if cit.is_isolated()
+                    || (state != DiseaseState::Exposed && state != DiseaseState::Infectious)
+                    || na::distance(&position, &cit.get_position()) > radius + cit.get_radius()
+                {
+                    continue;
+                }
+                if self.rng.gen::<f32>() < chance {
+                    cit.isolate();
+                }
+            }
+
+            let player = &mut self.player;
+            if !player.is_held()
+                && na::distance(&position, &player.get_position()) <= radius + player.get_radius()
+                && self.rng.gen::<f32>() < chance
+                && self.rng.gen::<f32>() >= player.get_resistance()
+            {
+                player.hold(testing.get_hold_time());
+                self.player_caught += 1;
+            }
+        }
+    }
+
+    // Vaccinated citizens are picked at random among the susceptible ones in the streets.
+    fn vaccination(&mut self) {
+        let due = self.authority.take_vaccines();
+        if due == 0 {
+            return;
+        }
+        let strains = if self.strains.len() >= strain::MAX_STRAINS {
+            u64::MAX
+        } else {
+            (1 << self.strains.len()) - 1
+        };
+        let mut candidates: Vec<usize> = self
+            .citizens
+            .iter()
+            .filter(|c| c.get_disease_state() == DiseaseState::Susceptible && !c.is_hidden())
+            .map(|c| c.get_id())
+            .collect();
+        for _ in 0..due {
+            if candidates.is_empty() {
+                break;
+            }
+            let id = candidates.swap_remove(self.rng.gen_range(0, candidates.len()));
+            self.citizens[id].vaccinate(strains);
+            self.vaccinated += 1;
+        }
+    }
+
+    // Advances the simulation by `DT` seconds.
+    pub fn step(&mut self, input: &Input) {
+        self.prev_player = self.player.get_position();
+        for (prev, cit) in self.prev_citizens.iter_mut().zip(self.citizens.iter()) {
+            *prev = cit.get_position();
+        }
+        for (prev, amb) in self.prev_ambulances.iter_mut().zip(self.ambulances.iter()) {
+            *prev = amb.get_position();
+        }
+
+        // Choose citizen randomly and change his angle.
+        if self.ticks.is_multiple_of(ANGLE_CHANGE_PERIOD) && !self.citizens.is_empty() {
+            let r = self.rng.gen_range(0, self.citizens.len());
+            self.citizens[r].change_angle(&mut self.rng);
+        }
+
+        // Patients in hospital can neither be met nor seen.
+        let citizens = &self.citizens;
+        self.grid.rebuild(
+            citizens
+                .iter()
+                .filter(|c| !c.is_hidden())
+                .map(|c| (c.get_id(), c.get_position())),
+        );
+
+        self.infection();
+        self.transmission();
+        self.treatment();
+        self.hiding();
+        self.dispatch();
+        self.drive_ambulances();
+        self.countermeasures();
+
+        let boundaries = self.settings.get_boundaries();
+        self.player.move_player(
+            boundaries.get_player(),
+            self.world,
+            input,
+            DT,
+            &mut self.rng,
+        );
+
+        if let Some(target) = input.aim {
+            self.player.aim_at(target);
+        }
+        // Sneezing wins when both buttons are held, a caught player cannot attack.
+        let pressed = if self.player.is_held() {
+            None
+        } else if input.sneeze {
+            Some(AttackKind::Sneeze)
+        } else if input.cough {
+            Some(AttackKind::Cough)
+        } else {
+            None
+        };
+        self.player.update_attack(pressed);
+
+        // Attacks leave clouds behind, which drift away and fade.
+        if let Some(cone) = self.player.get_cone() {
+            self.aerosol
+                .emit(cone, self.player.get_aerosol() * DT, &self.map);
+        }
+        self.aerosol.update(DT);
+
+        // Lockdowns slow citizens down.
+        let dt = DT * self.authority.get_mobility();
+        for cit in self.citizens.iter_mut() {
+            cit.move_being(boundaries.get_citizens(), self.world, dt, &mut self.rng);
+        }
+
+        for amb in self.ambulances.iter_mut() {
+            amb.move_being(boundaries.get_ambulances(), self.world, DT, &mut self.rng);
+        }
+
+        self.collisions();
+
+        self.ticks += 1;
+    }
+}
+
+// Cases the health authority knows about.
+fn is_known_case(cit: Citizen) -> bool {
+    cit.get_disease_state() == DiseaseState::Infectious && !cit.is_asymptomatic()
+}
+
+// Place of the known case with the most other known cases around it.
+fn hotspot(citizens: &[Citizen], grid: &SpatialGrid, radius: f32) -> Option<na::Point2<f32>> {
+    citizens
+        .iter()
+        .filter(|c| is_known_case(**c) && !c.is_hidden())
+        .map(|c| {
+            let around = grid
+                .query_radius(c.get_position(), radius)
+                .into_iter()
+                .filter(|&id| is_known_case(citizens[id]))
+                .count();
+            (c.get_position(), around)
+        })
+        .fold(
+            None,
+            |best: Option<(na::Point2<f32>, usize)>, (p, n)| match best {
+                Some((_, m)) if m >= n => best,
+                _ => Some((p, n)),
+            },
+        )
+        .map(|(p, _)| p)
+}
+
+// Moves a circle, which was at `from` before the step, out of the areas the
+// health authority closed. Closures only stop beings from going in, the ones
+// inside when the area was closed stay there.
+fn close_off(
+    authority: &Authority,
+    from: na::Point2<f32>,
+    center: na::Point2<f32>,
+    radius: f32,
+) -> (na::Point2<f32>, Option<na::Vector2<f32>>) {
+    let mut center = center;
+    let mut hit = None;
+    for closure in authority.get_closures() {
+        if closure.get_area().contains(from) {
+            continue;
+        }
+        if let Some((c, normal)) = closure.get_area().push_out(center, radius) {
+            center = c;
+            hit = Some(normal);
+        }
+    }
+    (center, hit)
+}
+
+// Id of the item closest to `point`.
+fn nearest<I: Iterator<Item = (usize, na::Point2<f32>)>>(
+    items: I,
+    point: na::Point2<f32>,
+) -> Option<usize> {
+    items
+        .map(|(i, p)| (i, na::distance(&p, &point)))
+        .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+        .map(|(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authority::Measure;
+    use crate::map::Obstacle;
+
+    // Slow citizens and no cellars nor health authority, so only the player changes the town.
+    fn quiet_settings(citizens: i32) -> GameSettings {
+        GameSettings::from_toml(&format!(
+            "citizen_quantity = {}\n\
+             citizen_speed = 10.0\n\
+             [cellars]\n\
+             quantity = 0\n\
+             [authority]\n\
+             strategy = \"none\"\n",
+            citizens
+        ))
+        .unwrap()
+    }
+
+    // Walks around in circles, sneezing every other second.
+    fn scripted_input(tick: u64) -> Input {
+        let phase = (tick / 60) % 4;
+        Input {
+            left: phase == 0,
+            up: phase == 1,
+            right: phase == 2,
+            down: phase == 3,
+            sneeze: (tick / 60).is_multiple_of(2),
+            cough: false,
+            aim: None,
+        }
+    }
+
+    fn positions(sim: &Simulation) -> Vec<na::Point2<f32>> {
+        sim.get_citizens()
+            .iter()
+            .map(|c| c.get_position())
+            .collect()
+    }
+
+    #[test]
+    fn sneezing_near_citizen_infects_it() {
+        let mut sim = Simulation::new(quiet_settings(1), 3);
+        let player = sim.get_player().get_position();
+        let target = player + na::Vector2::new(45.0, 0.0);
+        sim.citizens[0].set_position(target);
+        let before = sim.get_stats();
+        assert_eq!(before.susceptible, 1);
+        assert_eq!(before.infected_by_player, 0);
+
+        let input = Input {
+            sneeze: true,
+            aim: Some(target),
+            ..Input::default()
+        };
+        for _ in 0..120 {
+            sim.step(&input);
+        }
+
+        let stats = sim.get_stats();
+        assert_eq!(stats.ticks, 120);
+        assert_eq!(stats.susceptible, 0);
+        assert_eq!(stats.infected_by_player, 1);
+        assert_ne!(
+            sim.get_citizens()[0].get_disease_state(),
+            DiseaseState::Susceptible
+        );
+        assert_ne!(sim.get_citizens()[0].get_position(), target);
+    }
+
+    #[test]
+    fn infectious_citizens_infect_their_neighbours() {
+        let settings = GameSettings::from_toml(
+            "citizen_quantity = 3\n\
+             citizen_speed = 0.0\n\
+             [cellars]\n\
+             quantity = 0\n\
+             [authority]\n\
+             strategy = \"none\"\n\
+             [disease]\n\
+             transmission_probability = 1.0\n\
+             [mutation]\n\
+             probability = 0.0\n",
+        )
+        .unwrap();
+        let mut sim = Simulation::new(settings, 5);
+        // Citizens of radius 20 pass the disease up to 45 units away.
+        for (id, &x) in [200.0, 240.0, 300.0].iter().enumerate() {
+            let p = na::Point2::new(x, 100.0);
+            place_citizen(&mut sim, id, p, p);
+        }
+        let strain = sim.strains[0];
+        let disease = sim.settings.get_disease();
+        assert!(sim.citizens[0].become_exposed(InfectionSource::Player, strain, &mut sim.rng));
+        for _ in 0..strain.get_incubation_period() {
+            sim.citizens[0].progress_disease(disease, &mut sim.rng);
+        }
+        assert_eq!(
+            sim.get_citizens()[0].get_disease_state(),
+            DiseaseState::Infectious
+        );
+
+        for _ in 0..60 {
+            sim.step(&Input::default());
+        }
+        let near = sim.get_citizens()[1];
+        assert_ne!(near.get_disease_state(), DiseaseState::Susceptible);
+        assert!(matches!(
+            near.get_infection_source(),
+            Some(InfectionSource::Citizen(0))
+        ));
+        assert_eq!(
+            sim.get_citizens()[2].get_disease_state(),
+            DiseaseState::Susceptible
+        );
+        assert_eq!(sim.get_citizens()[0].get_cases(), 1);
+        assert_eq!(sim.get_stats().infected_by_player, 0);
+    }
+
+    #[test]
+    fn player_follows_input() {
+        let mut sim = Simulation::new(quiet_settings(0), 3);
+        let start = sim.get_player().get_position();
+        let input = Input {
+            right: true,
+            ..Input::default()
+        };
+        for _ in 0..30 {
+            sim.step(&input);
+        }
+        let end = sim.get_player().get_position();
+        assert!(end.x > start.x);
+        assert!((end.y - start.y).abs() < 1e-3);
+    }
+
+    #[test]
+    fn walking_around_comes_back() {
+        let mut sim = Simulation::new(GameSettings::default(), 3);
+        let start = sim.get_player().get_position();
+        for tick in 0..240 {
+            sim.step(&scripted_input(tick));
+        }
+        assert_eq!(sim.get_ticks(), 240);
+        assert!(na::distance(&start, &sim.get_player().get_position()) < 1e-3);
+    }
+
+    #[test]
+    fn autopilot_infects_citizens() {
+        let mut sim = Simulation::new(quiet_settings(10), 7);
+        for _ in 0..1200 {
+            let input = sim.autopilot();
+            sim.step(&input);
+        }
+        assert!(sim.get_stats().infected_by_player > 0);
+    }
+
+    // Health authority which closes areas and does nothing else.
+    fn closing_settings() -> GameSettings {
+        GameSettings::from_toml(
+            "citizen_quantity = 2\n\
+             citizen_speed = 0.0\n\
+             [cellars]\n\
+             quantity = 0\n\
+             [authority.masks]\n\
+             trigger = 1.0\n\
+             [authority.lockdown]\n\
+             trigger = 1.0\n\
+             [authority.testing]\n\
+             trigger = 1.0\n\
+             [authority.vaccination]\n\
+             trigger = 1.0\n\
+             [authority.closure]\n\
+             trigger = 0.0\n\
+             size = 150.0\n",
+        )
+        .unwrap()
+    }
+
+    fn close_area(sim: &mut Simulation, center: na::Point2<f32>) {
+        let stats = sim.get_stats();
+        assert_eq!(
+            sim.authority.review(&stats, || Some(center)),
+            Some(Measure::Closure)
+        );
+    }
+
+    fn place_citizen(sim: &mut Simulation, id: usize, from: na::Point2<f32>, to: na::Point2<f32>) {
+        sim.prev_citizens[id] = from;
+        sim.citizens[id].set_position(to);
+    }
+
+    #[test]
+    fn closures_only_stop_beings_going_in() {
+        let mut sim = Simulation::new(closing_settings(), 0);
+        let center = na::Point2::new(400.0, 300.0);
+        assert_eq!(sim.get_player().get_position(), center);
+        close_area(&mut sim, center);
+
+        // The area spans from 325 to 475 in both directions around the center.
+        let inside = na::Point2::new(350.0, 320.0);
+        place_citizen(&mut sim, 0, inside, inside);
+        place_citizen(
+            &mut sim,
+            1,
+            na::Point2::new(300.0, 300.0),
+            na::Point2::new(340.0, 300.0),
+        );
+        sim.collisions();
+        assert_eq!(sim.get_citizens()[0].get_position(), inside);
+        assert!(sim.get_citizens()[1].get_position().x <= 305.0 + 1e-3);
+        assert_eq!(sim.get_player().get_position(), center);
+
+        // The player walks out of the area, but cannot come back.
+        let right = Input {
+            right: true,
+            ..Input::default()
+        };
+        for _ in 0..60 {
+            sim.step(&right);
+        }
+        assert!(sim.get_player().get_position().x > 495.0);
+        let left = Input {
+            left: true,
+            ..Input::default()
+        };
+        for _ in 0..60 {
+            sim.step(&left);
+        }
+        assert!(sim.get_player().get_position().x >= 495.0 - 1e-3);
+        assert_eq!(sim.get_citizens()[0].get_position(), inside);
+    }
+
+    #[test]
+    fn closures_do_not_push_into_buildings() {
+        let building = Obstacle::rect(250.0, 200.0, 75.0, 200.0);
+        let map = Map::new(vec![building.clone()]);
+        let mut sim = Simulation::with_map(closing_settings(), map, 0);
+        close_area(&mut sim, na::Point2::new(400.0, 300.0));
+
+        // Pushed out of the area to the left, into the building.
+        place_citizen(
+            &mut sim,
+            0,
+            na::Point2::new(200.0, 100.0),
+            na::Point2::new(330.0, 300.0),
+        );
+        sim.collisions();
+        let cit = sim.get_citizens()[0];
+        assert!(!building.contains(cit.get_position()));
+        assert!(!sim
+            .get_map()
+            .is_blocked(cit.get_position(), cit.get_radius() - 0.1));
+    }
+
+    #[test]
+    fn citizens_hide_in_cellars_while_there_is_room() {
+        let settings = GameSettings::from_toml(
+            "citizen_quantity = 3\n\
+             citizen_speed = 100.0\n\
+             [cellars]\n\
+             quantity = 1\n\
+             capacity = 2\n\
+             hide_time = 120\n\
+             alarm_radius = 300.0\n\
+             [authority]\n\
+             strategy = \"none\"\n",
+        )
+        .unwrap();
+        let mut sim = Simulation::new(settings, 11);
+        let door = na::Point2::new(200.0, 300.0);
+        sim.cellars[0] = cellar::new(settings.get_cellars(), door, 40.0);
+        for (id, &y) in [250.0, 300.0, 350.0].iter().enumerate() {
+            let p = na::Point2::new(300.0, y);
+            place_citizen(&mut sim, id, p, p);
+        }
+
+        // Sneezing away from everybody sends them running.
+        let sneeze = Input {
+            sneeze: true,
+            aim: Some(na::Point2::new(800.0, 300.0)),
+            ..Input::default()
+        };
+        // The alarm is heard in the step after the sneeze starts.
+        sim.step(&sneeze);
+        sim.step(&sneeze);
+        assert!(sim
+            .get_citizens()
+            .iter()
+            .all(|c| c.get_shelter() == Some(0)));
+        let mut ticks = 2;
+        while sim.get_cellars()[0].get_hidden() < 2 {
+            sim.step(&Input::default());
+            ticks += 1;
+            assert!(ticks < 300);
+        }
+        let hidden: Vec<usize> = (0..3)
+            .filter(|&id| sim.get_citizens()[id].is_in_cellar())
+            .collect();
+        assert_eq!(hidden.len(), 2);
+        for _ in 0..60 {
+            sim.step(&Input::default());
+        }
+        assert_eq!(sim.get_cellars()[0].get_hidden(), 2);
+        assert_eq!(sim.get_stats().hidden, 2);
+        let outside = (0..3).find(|id| !hidden.contains(id)).unwrap();
+        assert!(!sim.get_citizens()[outside].is_in_cellar());
+        assert_eq!(sim.get_citizens()[outside].get_shelter(), None);
+
+        // Hidden citizens are neither met nor reached by a sneeze at the door.
+        assert!(sim
+            .query_radius(door, 100.0)
+            .iter()
+            .all(|id| !hidden.contains(id)));
+        sim.player.set_position(door);
+        for _ in 0..30 {
+            let input = Input {
+                aim: Some(door + na::Vector2::new(0.0, 1.0)),
+                ..sneeze
+            };
+            sim.step(&input);
+        }
+        for &id in &hidden {
+            assert_eq!(
+                sim.get_citizens()[id].get_disease_state(),
+                DiseaseState::Susceptible
+            );
+        }
+
+        // They come out after `hide_time` ticks.
+        for _ in 0..60 {
+            sim.step(&Input::default());
+        }
+        assert_eq!(sim.get_cellars()[0].get_hidden(), 0);
+        for &id in &hidden {
+            let cit = sim.get_citizens()[id];
+            assert!(!cit.is_in_cellar());
+            assert!(sim
+                .query_radius(cit.get_position(), cit.get_radius())
+                .contains(&id));
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_same_run() {
+        let settings = GameSettings::default();
+        let mut a = Simulation::new(settings, 42);
+        let mut b = Simulation::new(settings, 42);
+        for tick in 0..300 {
+            let input = scripted_input(tick);
+            a.step(&input);
+            b.step(&input);
+        }
+        assert_eq!(a.get_stats(), b.get_stats());
+        assert_eq!(positions(&a), positions(&b));
+        assert_eq!(a.get_player().get_position(), b.get_player().get_position());
+    }
+
+    #[test]
+    fn different_seeds_diverge() {
+        let settings = GameSettings::default();
+        let mut a = Simulation::new(settings, 1);
+        let mut b = Simulation::new(settings, 2);
+        for tick in 0..300 {
+            let input = scripted_input(tick);
+            a.step(&input);
+            b.step(&input);
+        }
+        assert_ne!(positions(&a), positions(&b));
+    }
+}
@@ -0,0 +1,128 @@
+use ggez::nalgebra as na;
+
+// Uniform grid over the world rectangle. Points outside of the rectangle
+// are kept in the border cells.
+pub struct SpatialGrid {
+    cell_size: f32,
+    cols: usize,
+    rows: usize,
+    cells: Vec<Vec<(usize, na::Point2<f32>)>>,
+}
+
+impl SpatialGrid {
+    pub fn new(width: f32, height: f32, cell_size: f32) -> SpatialGrid {
+        let cell_size = cell_size.max(1.0);
+        let cols = ((width / cell_size).ceil() as usize).max(1);
+        let rows = ((height / cell_size).ceil() as usize).max(1);
+        SpatialGrid {
+            cell_size,
+            cols,
+            rows,
+            cells: vec![Vec::new(); cols * rows],
+        }
+    }
+
+    fn cell_coords(&self, p: na::Point2<f32>) -> (usize, usize) {
+        let clamp = |v: f32, n: usize| -> usize {
+            if v <= 0.0 {
+                0
+            } else {
+                (v as usize).min(n - 1)
+            }
+        };
+        (
+            clamp(p.x / self.cell_size, self.cols),
+            clamp(p.y / self.cell_size, self.rows),
+        )
+    }
+
+    pub fn clear(&mut self) {
+        for cell in self.cells.iter_mut() {
+            cell.clear();
+        }
+    }
+
+    pub fn insert(&mut self, id: usize, p: na::Point2<f32>) {
+        let (cx, cy) = self.cell_coords(p);
+        self.cells[cy * self.cols + cx].push((id, p));
+    }
+
+    pub fn rebuild<I: IntoIterator<Item = (usize, na::Point2<f32>)>>(&mut self, items: I) {
+        self.clear();
+        for (id, p) in items {
+            self.insert(id, p);
+        }
+    }
+
+    // Ids of all points not further than `r` from `point`.
+    pub fn query_radius(&self, point: na::Point2<f32>, r: f32) -> Vec<usize> {
+        let mut found = Vec::new();
+        let (min_x, min_y) = self.cell_coords(na::Point2::new(point.x - r, point.y - r));
+        let (max_x, max_y) = self.cell_coords(na::Point2::new(point.x + r, point.y + r));
+        for cy in min_y..=max_y {
+            for cx in min_x..=max_x {
+                for (id, p) in self.cells[cy * self.cols + cx].iter() {
+                    if na::distance(&point, p) <= r {
+                        found.push(*id);
+                    }
+                }
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn brute_force(points: &[na::Point2<f32>], point: na::Point2<f32>, r: f32) -> Vec<usize> {
+        (0..points.len())
+            .filter(|&i| na::distance(&point, &points[i]) <= r)
+            .collect()
+    }
+
+    #[test]
+    fn query_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let (width, height, cell_size) = (200.0, 100.0, 20.0);
+        let mut points: Vec<na::Point2<f32>> = (0..200)
+            .map(|_| na::Point2::new(rng.gen_range(0.0, width), rng.gen_range(0.0, height)))
+            .collect();
+        // Corners of cells and edges of the world.
+        for i in 0..=10 {
+            for j in 0..=5 {
+                points.push(na::Point2::new(i as f32 * cell_size, j as f32 * cell_size));
+            }
+        }
+        // Points outside of the world.
+        points.extend(
+            [
+                (-30.0, 50.0),
+                (230.0, 50.0),
+                (100.0, -5.0),
+                (100.0, 140.0),
+                (-1.0, -1.0),
+                (500.0, 500.0),
+            ]
+            .iter()
+            .map(|&(x, y)| na::Point2::new(x, y)),
+        );
+
+        let mut grid = SpatialGrid::new(width, height, cell_size);
+        grid.rebuild(points.iter().cloned().enumerate());
+
+        let mut queries = points.clone();
+        queries.push(na::Point2::new(-50.0, -50.0));
+        queries.push(na::Point2::new(260.0, 130.0));
+        for &point in queries.iter() {
+            for &r in [0.0, 5.0, 20.0, 35.0, 300.0].iter() {
+                let mut found = grid.query_radius(point, r);
+                found.sort();
+                assert_eq!(found, brute_force(&points, point, r), "{} {}", point, r);
+            }
+        }
+    }
+}
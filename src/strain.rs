@@ -0,0 +1,217 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::gamesettings::SettingsError;
+
+// Immunity to strains is kept in a bit mask, so no more strains can appear.
+pub const MAX_STRAINS: usize = 64;
+
+// Variant of the virus. `transmissibility` multiplies the chance of passing it
+// between citizens and the dose given by the player, `severity` multiplies the
+// death rate and `incubation_period` is given in ticks. `immune_escape` is the
+// chance of infecting a citizen immune to other strains.
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Strain {
+    // Number of the strain in the order strains appeared, the one of the player is 0.
+    #[serde(skip)]
+    id: usize,
+    // Strain this one mutated from.
+    #[serde(skip)]
+    parent: Option<usize>,
+    transmissibility: f32,
+    severity: f32,
+    incubation_period: u32,
+    immune_escape: f32,
+}
+
+impl Strain {
+    pub fn get_id(self) -> usize {
+        self.id
+    }
+
+    pub fn get_parent(self) -> Option<usize> {
+        self.parent
+    }
+
+    pub fn get_transmissibility(self) -> f32 {
+        self.transmissibility
+    }
+
+    pub fn get_severity(self) -> f32 {
+        self.severity
+    }
+
+    pub fn get_incubation_period(self) -> u32 {
+        self.incubation_period
+    }
+
+    pub fn get_immune_escape(self) -> f32 {
+        self.immune_escape
+    }
+
+    pub fn with_transmissibility(self, transmissibility: f32) -> Strain {
+        Strain {
+            transmissibility,
+            ..self
+        }
+    }
+
+    pub fn with_incubation_period(self, incubation_period: u32) -> Strain {
+        Strain {
+            incubation_period,
+            ..self
+        }
+    }
+
+    // New strain with the given id, its parameters changed at random by up to
+    // `variation` of their values. Immune escape changes by up to `variation`.
+    pub fn mutate<R: Rng + ?Sized>(self, id: usize, variation: f32, rng: &mut R) -> Strain {
+        let mut change = || {
+            if variation > 0.0 {
+                rng.gen_range(-variation, variation)
+            } else {
+                0.0
+            }
+        };
+        let transmissibility = (self.transmissibility * (1.0 + change())).max(0.0);
+        let severity = (self.severity * (1.0 + change())).max(0.0);
+        let incubation_period = (self.incubation_period as f32 * (1.0 + change()))
+            .round()
+            .max(1.0) as u32;
+        let immune_escape = (self.immune_escape + change()).clamp(0.0, 1.0);
+        Strain {
+            id,
+            parent: Some(self.id),
+            transmissibility,
+            severity,
+            incubation_period,
+            immune_escape,
+        }
+    }
+
+    pub fn validate(self) -> Result<(), String> {
+        if self.transmissibility < 0.0 || self.severity < 0.0 {
+            return Err("transmissibility and severity must not be negative".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.immune_escape) {
+            return Err("immune_escape must be between 0.0 and 1.0".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Default for Strain {
+    fn default() -> Self {
+        Strain {
+            id: 0,
+            parent: None,
+            transmissibility: 1.0,
+            severity: 1.0,
+            incubation_period: 120,
+            immune_escape: 0.0,
+        }
+    }
+}
+
+// Every transmission between citizens makes a new strain with the chance of
+// `probability`, see `Strain::mutate`.
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MutationParams {
+    probability: f32,
+    variation: f32,
+}
+
+impl MutationParams {
+    pub fn get_probability(self) -> f32 {
+        self.probability
+    }
+
+    pub fn get_variation(self) -> f32 {
+        self.variation
+    }
+}
+
+impl Default for MutationParams {
+    fn default() -> Self {
+        MutationParams {
+            probability: 0.02,
+            variation: 0.2,
+        }
+    }
+}
+
+// Named strains the player can pick before a round, a table for each of them.
+pub fn load_presets<P: AsRef<Path>>(path: P) -> Result<BTreeMap<String, Strain>, SettingsError> {
+    let content = fs::read_to_string(path)?;
+    let presets: BTreeMap<String, Strain> = toml::from_str(&content)?;
+    Ok(presets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn strain() -> Strain {
+        Strain {
+            transmissibility: 2.0,
+            severity: 0.5,
+            incubation_period: 100,
+            immune_escape: 0.95,
+            ..Strain::default()
+        }
+    }
+
+    #[test]
+    fn mutations_stay_within_variation() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let parent = strain();
+        let variation = 0.2;
+        for id in 1..1000 {
+            let child = parent.mutate(id, variation, &mut rng);
+            assert_eq!(child.get_id(), id);
+            assert_eq!(child.get_parent(), Some(0));
+            assert!((1.6..=2.4).contains(&child.get_transmissibility()));
+            assert!((0.4..=0.6).contains(&child.get_severity()));
+            assert!((80..=120).contains(&child.get_incubation_period()));
+            assert!((0.75..=1.0).contains(&child.get_immune_escape()));
+            assert!(child.validate().is_ok());
+        }
+    }
+
+    #[test]
+    fn mutations_keep_parameters_valid() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let parent = Strain {
+            incubation_period: 1,
+            immune_escape: 0.0,
+            ..strain()
+        };
+        for id in 1..1000 {
+            let child = parent.mutate(id, 1.0, &mut rng);
+            assert!(child.get_incubation_period() >= 1);
+            assert!(child.get_transmissibility() >= 0.0);
+            assert!(child.validate().is_ok());
+        }
+    }
+
+    #[test]
+    fn no_variation_copies_the_parent() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let parent = strain();
+        let child = parent.mutate(3, 0.0, &mut rng);
+        assert_eq!(child.get_transmissibility(), parent.get_transmissibility());
+        assert_eq!(child.get_severity(), parent.get_severity());
+        assert_eq!(
+            child.get_incubation_period(),
+            parent.get_incubation_period()
+        );
+        assert_eq!(child.get_immune_escape(), parent.get_immune_escape());
+    }
+}
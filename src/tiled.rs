@@ -0,0 +1,406 @@
+// Reading of maps made in the Tiled editor, https://www.mapeditor.org/, saved
+// either as TMX or as JSON. Only orthogonal maps with embedded tilesets and
+// CSV layer data are supported.
+use ggez::nalgebra as na;
+use serde::Deserialize;
+
+use crate::map::MapError;
+
+// Tiled keeps flip flags in the highest bits of tile ids.
+const GID_MASK: u32 = 0x1FFF_FFFF;
+
+pub enum Shape {
+    Rect,
+    Ellipse,
+    Point,
+    // Points are relative to the position of the object.
+    Polygon(Vec<na::Point2<f32>>),
+}
+
+pub struct TiledObject {
+    // Tiled calls it `type`, or `class` since version 1.9.
+    pub kind: String,
+    pub layer: String,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub shape: Shape,
+}
+
+pub struct Tileset {
+    pub first_gid: u32,
+    // Path as saved by Tiled, relative to the map file. The game looks it up
+    // in the `resources` directory, so maps are kept there with their images.
+    pub image: String,
+    pub image_width: f32,
+    pub image_height: f32,
+    pub tile_width: f32,
+    pub tile_height: f32,
+    pub columns: u32,
+    pub margin: f32,
+    pub spacing: f32,
+}
+
+pub struct TileLayer {
+    pub width: u32,
+    pub height: u32,
+    // Tile ids row by row, zero means no tile.
+    pub data: Vec<u32>,
+}
+
+pub struct TiledMap {
+    pub width: u32,
+    pub height: u32,
+    pub tile_width: f32,
+    pub tile_height: f32,
+    pub tilesets: Vec<Tileset>,
+    pub layers: Vec<TileLayer>,
+    pub objects: Vec<TiledObject>,
+}
+
+fn invalid(reason: &str) -> MapError {
+    MapError::Invalid(reason.to_string())
+}
+
+fn check_orientation(orientation: &str) -> Result<(), MapError> {
+    if orientation == "orthogonal" {
+        Ok(())
+    } else {
+        Err(invalid("only orthogonal maps are supported"))
+    }
+}
+
+fn parse_csv(text: &str) -> Result<Vec<u32>, MapError> {
+    text.split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            v.parse::<u32>()
+                .map(|gid| gid & GID_MASK)
+                .map_err(|_| invalid("layer data must be a list of tile ids"))
+        })
+        .collect()
+}
+
+fn parse_points(text: &str) -> Result<Vec<na::Point2<f32>>, MapError> {
+    text.split_whitespace()
+        .map(|pair| {
+            let mut coords = pair.split(',').map(|c| c.parse::<f32>());
+            match (coords.next(), coords.next()) {
+                (Some(Ok(x)), Some(Ok(y))) => Ok(na::Point2::new(x, y)),
+                _ => Err(invalid("polygon points must be `x,y` pairs")),
+            }
+        })
+        .collect()
+}
+
+fn attr<T: std::str::FromStr>(node: roxmltree::Node, name: &str) -> Result<T, MapError> {
+    node.attribute(name)
+        .ok_or_else(|| {
+            MapError::Invalid(format!(
+                "`{}` is missing in <{}>",
+                name,
+                node.tag_name().name()
+            ))
+        })?
+        .parse()
+        .map_err(|_| {
+            MapError::Invalid(format!(
+                "`{}` in <{}> is not a number",
+                name,
+                node.tag_name().name()
+            ))
+        })
+}
+
+fn attr_or<T: std::str::FromStr>(
+    node: roxmltree::Node,
+    name: &str,
+    default: T,
+) -> Result<T, MapError> {
+    if node.has_attribute(name) {
+        attr(node, name)
+    } else {
+        Ok(default)
+    }
+}
+
+pub fn from_tmx(content: &str) -> Result<TiledMap, MapError> {
+    let doc = roxmltree::Document::parse(content)?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "map" {
+        return Err(invalid("<map> element is missing"));
+    }
+    check_orientation(root.attribute("orientation").unwrap_or("orthogonal"))?;
+
+    let mut map = TiledMap {
+        width: attr(root, "width")?,
+        height: attr(root, "height")?,
+        tile_width: attr(root, "tilewidth")?,
+        tile_height: attr(root, "tileheight")?,
+        tilesets: Vec::new(),
+        layers: Vec::new(),
+        objects: Vec::new(),
+    };
+    for node in root.children().filter(|n| n.is_element()) {
+        if node.tag_name().name() == "tileset" {
+            map.tilesets.push(tmx_tileset(node)?);
+        }
+    }
+    tmx_layers(root, &mut map)?;
+    Ok(map)
+}
+
+fn tmx_tileset(node: roxmltree::Node) -> Result<Tileset, MapError> {
+    if node.has_attribute("source") {
+        return Err(invalid(
+            "external tilesets are not supported, embed them in the map",
+        ));
+    }
+    let image = node
+        .children()
+        .find(|n| n.has_tag_name("image"))
+        .ok_or_else(|| invalid("only tilesets made of a single image are supported"))?;
+    Ok(Tileset {
+        first_gid: attr(node, "firstgid")?,
+        image: image.attribute("source").unwrap_or_default().to_string(),
+        image_width: attr(image, "width")?,
+        image_height: attr(image, "height")?,
+        tile_width: attr(node, "tilewidth")?,
+        tile_height: attr(node, "tileheight")?,
+        columns: attr(node, "columns")?,
+        margin: attr_or(node, "margin", 0.0)?,
+        spacing: attr_or(node, "spacing", 0.0)?,
+    })
+}
+
+// Layers in groups are read as if they were not grouped.
+fn tmx_layers(parent: roxmltree::Node, map: &mut TiledMap) -> Result<(), MapError> {
+    for node in parent.children().filter(|n| n.is_element()) {
+        match node.tag_name().name() {
+            "layer" => {
+                let data = node
+                    .children()
+                    .find(|n| n.has_tag_name("data"))
+                    .ok_or_else(|| invalid("<layer> without <data>"))?;
+                if data.attribute("encoding") != Some("csv") {
+                    return Err(invalid("only CSV layer data is supported"));
+                }
+                map.layers.push(TileLayer {
+                    width: attr(node, "width")?,
+                    height: attr(node, "height")?,
+                    data: parse_csv(data.text().unwrap_or_default())?,
+                });
+            }
+            "objectgroup" => {
+                let layer = node.attribute("name").unwrap_or_default();
+                for object in node.children().filter(|n| n.has_tag_name("object")) {
+                    map.objects.push(tmx_object(object, layer)?);
+                }
+            }
+            "group" => tmx_layers(node, map)?,
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+fn tmx_object(node: roxmltree::Node, layer: &str) -> Result<TiledObject, MapError> {
+    let mut shape = Shape::Rect;
+    for child in node.children().filter(|n| n.is_element()) {
+        match child.tag_name().name() {
+            "ellipse" => shape = Shape::Ellipse,
+            "point" => shape = Shape::Point,
+            "polygon" => {
+                shape = Shape::Polygon(parse_points(child.attribute("points").unwrap_or_default())?)
+            }
+            _ => (),
+        }
+    }
+    Ok(TiledObject {
+        kind: node
+            .attribute("type")
+            .or_else(|| node.attribute("class"))
+            .unwrap_or_default()
+            .to_string(),
+        layer: layer.to_string(),
+        x: attr(node, "x")?,
+        y: attr(node, "y")?,
+        width: attr_or(node, "width", 0.0)?,
+        height: attr_or(node, "height", 0.0)?,
+        shape,
+    })
+}
+
+#[derive(Deserialize)]
+struct JsonMap {
+    #[serde(default = "orthogonal")]
+    orientation: String,
+    width: u32,
+    height: u32,
+    tilewidth: f32,
+    tileheight: f32,
+    #[serde(default)]
+    tilesets: Vec<JsonTileset>,
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+}
+
+fn orthogonal() -> String {
+    "orthogonal".to_string()
+}
+
+#[derive(Deserialize)]
+struct JsonTileset {
+    firstgid: u32,
+    source: Option<String>,
+    image: Option<String>,
+    #[serde(default)]
+    imagewidth: f32,
+    #[serde(default)]
+    imageheight: f32,
+    #[serde(default)]
+    tilewidth: f32,
+    #[serde(default)]
+    tileheight: f32,
+    #[serde(default)]
+    columns: u32,
+    #[serde(default)]
+    margin: f32,
+    #[serde(default)]
+    spacing: f32,
+}
+
+#[derive(Deserialize)]
+struct JsonLayer {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    width: u32,
+    #[serde(default)]
+    height: u32,
+    data: Option<serde_json::Value>,
+    #[serde(default)]
+    objects: Vec<JsonObject>,
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+}
+
+#[derive(Deserialize)]
+struct JsonObject {
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    class: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    #[serde(default)]
+    ellipse: bool,
+    #[serde(default)]
+    point: bool,
+    polygon: Option<Vec<JsonPoint>>,
+}
+
+#[derive(Deserialize)]
+struct JsonPoint {
+    x: f32,
+    y: f32,
+}
+
+pub fn from_json(content: &str) -> Result<TiledMap, MapError> {
+    let json: JsonMap = serde_json::from_str(content)?;
+    check_orientation(&json.orientation)?;
+
+    let mut map = TiledMap {
+        width: json.width,
+        height: json.height,
+        tile_width: json.tilewidth,
+        tile_height: json.tileheight,
+        tilesets: Vec::new(),
+        layers: Vec::new(),
+        objects: Vec::new(),
+    };
+    for tileset in json.tilesets {
+        if tileset.source.is_some() {
+            return Err(invalid(
+                "external tilesets are not supported, embed them in the map",
+            ));
+        }
+        map.tilesets.push(Tileset {
+            first_gid: tileset.firstgid,
+            image: tileset
+                .image
+                .ok_or_else(|| invalid("only tilesets made of a single image are supported"))?,
+            image_width: tileset.imagewidth,
+            image_height: tileset.imageheight,
+            tile_width: tileset.tilewidth,
+            tile_height: tileset.tileheight,
+            columns: tileset.columns,
+            margin: tileset.margin,
+            spacing: tileset.spacing,
+        });
+    }
+    json_layers(json.layers, &mut map)?;
+    Ok(map)
+}
+
+fn json_layers(layers: Vec<JsonLayer>, map: &mut TiledMap) -> Result<(), MapError> {
+    for layer in layers {
+        match layer.kind.as_str() {
+            "tilelayer" => {
+                let data = match layer.data {
+                    Some(serde_json::Value::Array(values)) => values
+                        .iter()
+                        .map(|v| {
+                            v.as_u64()
+                                .map(|gid| gid as u32 & GID_MASK)
+                                .ok_or_else(|| invalid("layer data must be a list of tile ids"))
+                        })
+                        .collect::<Result<Vec<u32>, MapError>>()?,
+                    _ => return Err(invalid("only CSV layer data is supported")),
+                };
+                map.layers.push(TileLayer {
+                    width: layer.width,
+                    height: layer.height,
+                    data,
+                });
+            }
+            "objectgroup" => {
+                for object in layer.objects {
+                    let shape = if let Some(points) = object.polygon {
+                        Shape::Polygon(points.iter().map(|p| na::Point2::new(p.x, p.y)).collect())
+                    } else if object.ellipse {
+                        Shape::Ellipse
+                    } else if object.point {
+                        Shape::Point
+                    } else {
+                        Shape::Rect
+                    };
+                    map.objects.push(TiledObject {
+                        kind: if object.kind.is_empty() {
+                            object.class
+                        } else {
+                            object.kind
+                        },
+                        layer: layer.name.clone(),
+                        x: object.x,
+                        y: object.y,
+                        width: object.width,
+                        height: object.height,
+                        shape,
+                    });
+                }
+            }
+            "group" => json_layers(layer.layers, map)?,
+            _ => (),
+        }
+    }
+    Ok(())
+}
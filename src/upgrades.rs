@@ -0,0 +1,461 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum UpgradeError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Save(toml::ser::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for UpgradeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UpgradeError::Io(e) => write!(f, "Cannot read upgrades: {}", e),
+            UpgradeError::Parse(e) => write!(f, "Cannot parse upgrades: {}", e),
+            UpgradeError::Save(e) => write!(f, "Cannot save progress: {}", e),
+            UpgradeError::Invalid(reason) => write!(f, "Invalid upgrades: {}", reason),
+        }
+    }
+}
+
+impl Error for UpgradeError {}
+
+impl From<io::Error> for UpgradeError {
+    fn from(e: io::Error) -> Self {
+        UpgradeError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for UpgradeError {
+    fn from(e: toml::de::Error) -> Self {
+        UpgradeError::Parse(e)
+    }
+}
+
+impl From<toml::ser::Error> for UpgradeError {
+    fn from(e: toml::ser::Error) -> Self {
+        UpgradeError::Save(e)
+    }
+}
+
+// What upgrades change, see `GameSettings::with_upgrades`. All but
+// `resistance` multiply a setting, effects of several upgrades stack.
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Effects {
+    // Reach of the sneeze.
+    sneeze_range: f32,
+    player_speed: f32,
+    // Distance at which citizens hear the player sneeze, lower is stealthier.
+    alarm_radius: f32,
+    // Transmissibility of the strain of the player.
+    transmissibility: f32,
+    // Chance of slipping away when the player is caught.
+    resistance: f32,
+}
+
+impl Effects {
+    pub fn get_sneeze_range(self) -> f32 {
+        self.sneeze_range
+    }
+
+    pub fn get_player_speed(self) -> f32 {
+        self.player_speed
+    }
+
+    pub fn get_alarm_radius(self) -> f32 {
+        self.alarm_radius
+    }
+
+    pub fn get_transmissibility(self) -> f32 {
+        self.transmissibility
+    }
+
+    pub fn get_resistance(self) -> f32 {
+        self.resistance
+    }
+
+    // Effects of both upgrades together. Each resistance is a separate
+    // chance of slipping away.
+    pub fn combine(self, other: Effects) -> Effects {
+        Effects {
+            sneeze_range: self.sneeze_range * other.sneeze_range,
+            player_speed: self.player_speed * other.player_speed,
+            alarm_radius: self.alarm_radius * other.alarm_radius,
+            transmissibility: self.transmissibility * other.transmissibility,
+            resistance: 1.0 - (1.0 - self.resistance) * (1.0 - other.resistance),
+        }
+    }
+
+    fn validate(self) -> Result<(), String> {
+        if self.sneeze_range < 0.0
+            || self.player_speed < 0.0
+            || self.alarm_radius < 0.0
+            || self.transmissibility < 0.0
+        {
+            return Err("multipliers must not be negative".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.resistance) {
+            return Err("resistance must be between 0.0 and 1.0".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Default for Effects {
+    fn default() -> Self {
+        Effects {
+            sneeze_range: 1.0,
+            player_speed: 1.0,
+            alarm_radius: 1.0,
+            transmissibility: 1.0,
+            resistance: 0.0,
+        }
+    }
+}
+
+// Upgrade bought with points, once all the ones it `requires` are bought.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Upgrade {
+    id: String,
+    name: String,
+    #[serde(default)]
+    description: String,
+    cost: u32,
+    #[serde(default)]
+    requires: Vec<String>,
+    #[serde(default)]
+    effects: Effects,
+}
+
+impl Upgrade {
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn get_cost(&self) -> u32 {
+        self.cost
+    }
+
+    pub fn get_requires(&self) -> &[String] {
+        &self.requires
+    }
+
+    pub fn get_effects(&self) -> Effects {
+        self.effects
+    }
+}
+
+// Points and upgrades the player has, kept between rounds.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Progress {
+    points: u32,
+    owned: Vec<String>,
+}
+
+impl Progress {
+    // A missing file is a fresh start.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Progress, UpgradeError> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(toml::from_str(&content)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Progress::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), UpgradeError> {
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn get_points(&self) -> u32 {
+        self.points
+    }
+
+    pub fn add_points(&mut self, points: u32) {
+        self.points += points;
+    }
+
+    pub fn owns(&self, id: &str) -> bool {
+        self.owned.iter().any(|owned| owned == id)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TreeDef {
+    #[serde(default)]
+    upgrade: Vec<Upgrade>,
+}
+
+// Upgrades in the order of the file, each one may only require the ones
+// before it.
+#[derive(Clone, Default)]
+pub struct UpgradeTree {
+    upgrades: Vec<Upgrade>,
+}
+
+impl UpgradeTree {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<UpgradeTree, UpgradeError> {
+        let content = fs::read_to_string(path)?;
+        UpgradeTree::from_toml(&content)
+    }
+
+    pub fn from_toml(content: &str) -> Result<UpgradeTree, UpgradeError> {
+        let def: TreeDef = toml::from_str(content)?;
+        let tree = UpgradeTree {
+            upgrades: def.upgrade,
+        };
+        tree.validate()?;
+        Ok(tree)
+    }
+
+    fn validate(&self) -> Result<(), UpgradeError> {
+        for (i, upgrade) in self.upgrades.iter().enumerate() {
+            let before = &self.upgrades[..i];
+            if before.iter().any(|u| u.id == upgrade.id) {
+                return Err(UpgradeError::Invalid(format!(
+                    "`{}` is defined twice",
+                    upgrade.id
+                )));
+            }
+            for required in upgrade.requires.iter() {
+                if !before.iter().any(|u| &u.id == required) {
+                    return Err(UpgradeError::Invalid(format!(
+                        "`{}` requires `{}`, which is not defined before it",
+                        upgrade.id, required
+                    )));
+                }
+            }
+            upgrade.effects.validate().map_err(|reason| {
+                UpgradeError::Invalid(format!("effects of `{}`: {}", upgrade.id, reason))
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn get_upgrades(&self) -> &[Upgrade] {
+        &self.upgrades
+    }
+
+    // All upgrades this one requires are bought.
+    pub fn is_unlocked(&self, index: usize, progress: &Progress) -> bool {
+        self.upgrades[index]
+            .requires
+            .iter()
+            .all(|required| progress.owns(required))
+    }
+
+    pub fn buy(&self, index: usize, progress: &mut Progress) -> Result<(), String> {
+        let upgrade = &self.upgrades[index];
+        if progress.owns(&upgrade.id) {
+            return Err(format!("{} is already bought", upgrade.name));
+        }
+        if !self.is_unlocked(index, progress) {
+            return Err(format!(
+                "{} requires {}",
+                upgrade.name,
+                upgrade.requires.join(", ")
+            ));
+        }
+        if progress.points < upgrade.cost {
+            return Err(format!(
+                "{} costs {} points, you have {}",
+                upgrade.name, upgrade.cost, progress.points
+            ));
+        }
+        progress.points -= upgrade.cost;
+        progress.owned.push(upgrade.id.clone());
+        Ok(())
+    }
+
+    // Effects of all bought upgrades. Ids missing from the tree are ignored,
+    // so saved progress survives changes of the file.
+    pub fn effects(&self, progress: &Progress) -> Effects {
+        self.upgrades
+            .iter()
+            .filter(|upgrade| progress.owns(&upgrade.id))
+            .fold(Effects::default(), |effects, upgrade| {
+                effects.combine(upgrade.effects)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TREE: &str = r#"
+        [[upgrade]]
+        id = "jogger"
+        name = "Jogger"
+        cost = 10
+        effects = { player_speed = 1.5 }
+
+        [[upgrade]]
+        id = "sprinter"
+        name = "Sprinter"
+        cost = 30
+        requires = ["jogger"]
+        effects = { player_speed = 2.0 }
+    "#;
+
+    fn invalid_reason(content: &str) -> String {
+        match UpgradeTree::from_toml(content) {
+            Err(UpgradeError::Invalid(reason)) => reason,
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("invalid tree was accepted"),
+        }
+    }
+
+    #[test]
+    fn shipped_tree_is_valid() {
+        assert!(
+            UpgradeTree::from_file(concat!(env!("CARGO_MANIFEST_DIR"), "/upgrades.toml")).is_ok()
+        );
+    }
+
+    #[test]
+    fn rejects_duplicates() {
+        let reason = invalid_reason(
+            r#"
+            [[upgrade]]
+            id = "jogger"
+            name = "Jogger"
+            cost = 10
+
+            [[upgrade]]
+            id = "jogger"
+            name = "Runner"
+            cost = 20
+            "#,
+        );
+        assert!(reason.contains("defined twice"));
+    }
+
+    #[test]
+    fn rejects_requirements_defined_later() {
+        let reason = invalid_reason(
+            r#"
+            [[upgrade]]
+            id = "sprinter"
+            name = "Sprinter"
+            cost = 30
+            requires = ["jogger"]
+
+            [[upgrade]]
+            id = "jogger"
+            name = "Jogger"
+            cost = 10
+            "#,
+        );
+        assert!(reason.contains("`sprinter` requires `jogger`"));
+        let reason = invalid_reason(
+            r#"
+            [[upgrade]]
+            id = "jogger"
+            name = "Jogger"
+            cost = 10
+            requires = ["jogger"]
+            "#,
+        );
+        assert!(reason.contains("`jogger` requires `jogger`"));
+    }
+
+    #[test]
+    fn rejects_negative_effects() {
+        let reason = invalid_reason(
+            r#"
+            [[upgrade]]
+            id = "slug"
+            name = "Slug"
+            cost = 10
+            effects = { player_speed = -1.0 }
+            "#,
+        );
+        assert!(reason.contains("`slug`"));
+    }
+
+    #[test]
+    fn buying_takes_points() {
+        let tree = UpgradeTree::from_toml(TREE).unwrap();
+        let mut progress = Progress::default();
+        progress.add_points(35);
+
+        // Locked until the required upgrade is bought.
+        assert!(!tree.is_unlocked(1, &progress));
+        assert!(tree.buy(1, &mut progress).is_err());
+        assert_eq!(progress.get_points(), 35);
+
+        assert!(tree.buy(0, &mut progress).is_ok());
+        assert_eq!(progress.get_points(), 25);
+        assert!(progress.owns("jogger"));
+        assert!(tree.buy(0, &mut progress).is_err());
+        assert_eq!(progress.get_points(), 25);
+
+        // Not enough points left.
+        assert!(tree.is_unlocked(1, &progress));
+        assert!(tree.buy(1, &mut progress).is_err());
+        assert_eq!(progress.get_points(), 25);
+        progress.add_points(5);
+        assert!(tree.buy(1, &mut progress).is_ok());
+        assert_eq!(progress.get_points(), 0);
+        assert!(progress.owns("sprinter"));
+    }
+
+    #[test]
+    fn effects_of_bought_upgrades_stack() {
+        let tree = UpgradeTree::from_toml(TREE).unwrap();
+        let mut progress = Progress::default();
+        assert_eq!(tree.effects(&progress).get_player_speed(), 1.0);
+        progress.add_points(40);
+        tree.buy(0, &mut progress).unwrap();
+        assert_eq!(tree.effects(&progress).get_player_speed(), 1.5);
+        tree.buy(1, &mut progress).unwrap();
+        assert_eq!(tree.effects(&progress).get_player_speed(), 3.0);
+    }
+
+    #[test]
+    fn resistances_are_separate_chances() {
+        let tree = UpgradeTree::from_toml(
+            r#"
+            [[upgrade]]
+            id = "poker_face"
+            name = "Poker face"
+            cost = 0
+            effects = { resistance = 0.5 }
+
+            [[upgrade]]
+            id = "escape_artist"
+            name = "Escape artist"
+            cost = 0
+            effects = { resistance = 0.5 }
+            "#,
+        )
+        .unwrap();
+        let mut progress = Progress::default();
+        tree.buy(0, &mut progress).unwrap();
+        tree.buy(1, &mut progress).unwrap();
+        assert_eq!(tree.effects(&progress).get_resistance(), 0.75);
+        assert!(UpgradeTree::from_toml(
+            "[[upgrade]]\nid = \"x\"\nname = \"X\"\ncost = 0\neffects = { resistance = 1.5 }"
+        )
+        .is_err());
+    }
+}